@@ -0,0 +1,62 @@
+use std::sync::OnceLock;
+
+use crate::glam::*;
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear_v3(value: Vec3) -> Vec3 {
+    Vec3::new(srgb_to_linear(value.x), srgb_to_linear(value.y), srgb_to_linear(value.z))
+}
+
+pub fn linear_to_srgb_v3(value: Vec3) -> Vec3 {
+    Vec3::new(linear_to_srgb(value.x), linear_to_srgb(value.y), linear_to_srgb(value.z))
+}
+
+// Decoding 8-bit sRGB texels is hot in sampling, so the 256 possible values are tabulated once
+pub fn srgb_u8_to_linear(value: u8) -> f32 {
+    static LUT: OnceLock<[f32; 256]> = OnceLock::new();
+
+    let lut = LUT.get_or_init(|| {
+        let mut lut = [0.0; 256];
+        for (i, entry) in lut.iter_mut().enumerate() {
+            *entry = srgb_to_linear(i as f32 / 255.0);
+        }
+        lut
+    });
+
+    lut[value as usize]
+}
+
+pub fn linear_to_srgb_u8(value: f32) -> u8 {
+    (linear_to_srgb(value.clamp(0.0, 1.0)) * 255.0 + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trip() {
+        for value in 0..=255u8 {
+            assert_eq!(linear_to_srgb_u8(srgb_u8_to_linear(value)), value);
+        }
+        for value in [0.0, 0.002, 0.04, 0.5, 1.0] {
+            assert!((srgb_to_linear(linear_to_srgb(value)) - value).abs() < 1e-6);
+        }
+        assert!((srgb_to_linear(0.5) - 0.21404).abs() < 1e-5);
+    }
+}
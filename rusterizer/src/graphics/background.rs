@@ -0,0 +1,191 @@
+use crate::glam::*;
+use crate::window::FrameBuffer;
+use crate::graphics::{ColorBuffer, Environment, direction_to_equirect, sample_equirect};
+use crate::resources::Image;
+use crate::Shared;
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Six faces in the order +X, -X, +Y, -Y, +Z, -Z, laid out like OpenGL cubemaps
+pub struct Cubemap {
+    pub faces: [Shared<Image>; 6]
+}
+
+impl Cubemap {
+    pub fn new(faces: [Shared<Image>; 6]) -> Self {
+        Cubemap {
+            faces: faces
+        }
+    }
+
+    pub fn sample(&self, direction: Vec3) -> Vec3 {
+        let abs = direction.abs();
+        let (face, sc, tc, ma) = if abs.x >= abs.y && abs.x >= abs.z {
+            if direction.x > 0.0 { (0, -direction.z, -direction.y, abs.x) } else { (1, direction.z, -direction.y, abs.x) }
+        } else if abs.y >= abs.z {
+            if direction.y > 0.0 { (2, direction.x, direction.z, abs.y) } else { (3, direction.x, -direction.z, abs.y) }
+        } else if direction.z > 0.0 {
+            (4, direction.x, -direction.y, abs.z)
+        } else {
+            (5, -direction.x, -direction.y, abs.z)
+        };
+
+        let image = self.faces[face].as_ref();
+        let u = (sc / ma) * 0.5 + 0.5;
+        let v = (tc / ma) * 0.5 + 0.5;
+
+        let x = (u * image.dimensions.x as f32 - 0.5).clamp(0.0, (image.dimensions.x - 1) as f32);
+        let y = (v * image.dimensions.y as f32 - 0.5).clamp(0.0, (image.dimensions.y - 1) as f32);
+        let x0 = x as usize;
+        let y0 = y as usize;
+        let x1 = (x0 + 1).min(image.dimensions.x as usize - 1);
+        let y1 = (y0 + 1).min(image.dimensions.y as usize - 1);
+
+        let t = image.texel(x0, y0).lerp(image.texel(x1, y0), x - x0 as f32);
+        let b = image.texel(x0, y1).lerp(image.texel(x1, y1), x - x0 as f32);
+        t.lerp(b, y - y0 as f32).xyz()
+    }
+}
+
+pub enum Background {
+    SolidColor(Vec3),
+    // Blends from the horizon towards the zenith above and towards the ground below
+    Gradient { zenith: Vec3, horizon: Vec3, ground: Vec3 },
+    // Gradient sky with a sun disk and a forward scattering halo around it
+    Sky { sun_direction: Vec3, sun_color: Vec3, sun_size: f32, zenith: Vec3, horizon: Vec3, ground: Vec3 },
+    Equirect { image: Shared<Image>, intensity: f32 },
+    // Level of the prefiltered specular chain, 0 is the sharpest
+    Environment { environment: Shared<Environment>, level: f32 },
+    Cubemap { cubemap: Shared<Cubemap>, intensity: f32 }
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::SolidColor(Vec3::ZERO)
+    }
+}
+
+impl Background {
+    pub fn sky(sun_direction: Vec3) -> Self {
+        Background::Sky {
+            sun_direction: sun_direction,
+            sun_color: Vec3::new(20.0, 18.0, 15.0),
+            sun_size: 0.02,
+            zenith: Vec3::new(0.15, 0.3, 0.65),
+            horizon: Vec3::new(0.6, 0.7, 0.8),
+            ground: Vec3::new(0.2, 0.18, 0.16)
+        }
+    }
+
+    pub fn sample(&self, direction: Vec3) -> Vec3 {
+        let direction = direction.normalize();
+        match self {
+            Background::SolidColor(color) => *color,
+            Background::Gradient { zenith, horizon, ground } => Self::gradient(direction, *zenith, *horizon, *ground),
+            Background::Sky { sun_direction, sun_color, sun_size, zenith, horizon, ground } => {
+                let sky = Self::gradient(direction, *zenith, *horizon, *ground);
+
+                // The light direction points away from the sun
+                let cos_angle = direction.dot(-sun_direction.normalize());
+                let angle = cos_angle.clamp(-1.0, 1.0).acos();
+                let disk = 1.0 - smoothstep(*sun_size * 0.8, *sun_size, angle);
+                let halo = cos_angle.max(0.0).powf(64.0) * 0.05 + cos_angle.max(0.0).powf(8.0) * 0.02;
+                let above = smoothstep(-0.02, 0.0, direction.y);
+
+                sky + *sun_color * (disk + halo) * above
+            },
+            Background::Equirect { image, intensity } => sample_equirect(&image.as_ref(), direction_to_equirect(direction)) * *intensity,
+            Background::Environment { environment, level } => environment.as_ref().sample(direction, *level),
+            Background::Cubemap { cubemap, intensity } => cubemap.as_ref().sample(direction) * *intensity
+        }
+    }
+
+    fn gradient(direction: Vec3, zenith: Vec3, horizon: Vec3, ground: Vec3) -> Vec3 {
+        if direction.y >= 0.0 {
+            horizon.lerp(zenith, direction.y.sqrt())
+        } else {
+            horizon.lerp(ground, (-direction.y * 4.0).min(1.0).sqrt())
+        }
+    }
+
+    // Fills every pixel that no geometry was drawn to, the view ray is reconstructed from the inverse view-projection
+    pub fn render(&self, color_buffer: &mut ColorBuffer, depth_buffer: &FrameBuffer, inv_view_proj: &Mat4) {
+        let width = color_buffer.width();
+        let height = color_buffer.height();
+        let test_depth = depth_buffer.width() == width && depth_buffer.height() == height;
+
+        for y in 0..height {
+            for x in 0..width {
+                if test_depth && depth_buffer.get_pixel(x, y) != u32::MAX {
+                    continue;
+                }
+
+                // Inverse of the screen mapping of the pipeline, which flips both axes
+                let ndc_x = 1.0 - 2.0 * (x as f32 + 0.5) / width as f32;
+                let ndc_y = 1.0 - 2.0 * (y as f32 + 0.5) / height as f32;
+                let near = inv_view_proj.project_point3(Vec3::new(ndc_x, ndc_y, 0.0));
+                let far = inv_view_proj.project_point3(Vec3::new(ndc_x, ndc_y, 1.0));
+
+                color_buffer.set_pixel(x, y, Vec4::from((self.sample(far - near), 1.0)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::{ImageData, TexelFormat};
+
+    #[test]
+    fn cubemap_faces_follow_the_major_axis() {
+        let faces = [0u8, 1, 2, 3, 4, 5].map(|face| Shared::new(Image::new(ImageData::U8(vec![face * 40, 0, 0]), IVec2::ONE, TexelFormat::RGB8)));
+        let cubemap = Cubemap::new(faces);
+
+        let directions = [Vec3::X, Vec3::NEG_X, Vec3::Y, Vec3::NEG_Y, Vec3::Z, Vec3::NEG_Z];
+        for (face, direction) in directions.iter().enumerate() {
+            let direction = *direction + Vec3::new(0.1, 0.2, 0.3) * 0.5;
+            assert_eq!((cubemap.sample(direction).x * 255.0).round() as usize, face * 40);
+        }
+    }
+
+    #[test]
+    fn gradient_blends_through_the_horizon() {
+        let (zenith, horizon, ground) = (Vec3::Z, Vec3::Y, Vec3::X);
+        let background = Background::Gradient { zenith: zenith, horizon: horizon, ground: ground };
+
+        assert_eq!(background.sample(Vec3::Y), zenith);
+        assert_eq!(background.sample(Vec3::X), horizon);
+        assert_eq!(background.sample(Vec3::NEG_Y), ground);
+    }
+
+    #[test]
+    fn sky_is_brightest_towards_the_sun() {
+        let background = Background::sky(Vec3::new(0.0, -1.0, -1.0));
+        let sun = background.sample(Vec3::new(0.0, 1.0, 1.0));
+        let away = background.sample(Vec3::new(0.0, 1.0, -1.0));
+        assert!(sun.min_element() > away.max_element() * 10.0, "{:?} {:?}", sun, away);
+    }
+
+    #[test]
+    fn only_empty_pixels_are_filled() {
+        let mut color_buffer = ColorBuffer::new(4, 4);
+        let mut depth_buffer = FrameBuffer::new(4, 4);
+        depth_buffer.clear(u32::MAX);
+        depth_buffer.set_pixel(1, 2, 0);
+
+        let inv_view_proj = Mat4::perspective_rh((60.0f32).to_radians(), 1.0, 0.1, 10.0).inverse();
+        Background::SolidColor(Vec3::ONE).render(&mut color_buffer, &depth_buffer, &inv_view_proj);
+        assert_eq!(color_buffer.get_pixel(1, 2), Vec4::ZERO);
+        assert_eq!(color_buffer.get_pixel(2, 1), Vec4::ONE);
+
+        // The top row looks up into the sky
+        let background = Background::Gradient { zenith: Vec3::ONE, horizon: Vec3::ZERO, ground: Vec3::ZERO };
+        background.render(&mut color_buffer, &depth_buffer, &inv_view_proj);
+        assert!(color_buffer.get_pixel(2, 0).x > 0.0);
+        assert_eq!(color_buffer.get_pixel(2, 3).x, 0.0);
+    }
+}
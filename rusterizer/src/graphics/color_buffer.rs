@@ -0,0 +1,73 @@
+use crate::glam::*;
+
+#[derive(Clone)]
+pub struct ColorBuffer {
+    data: Vec<Vec4>,
+    width: usize,
+    height: usize
+}
+
+impl ColorBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        ColorBuffer {
+            data: vec![Vec4::ZERO; width * height],
+            width: width,
+            height: height
+        }
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        if width != self.width || height != self.height {
+            *self = ColorBuffer::new(width, height);
+        }
+    }
+
+    pub fn clear(&mut self, value: Vec4) {
+        for i in &mut self.data { *i = value; }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, value: Vec4) {
+        self.data[y * self.width + x] = value;
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> Vec4 {
+        self.data[y * self.width + x]
+    }
+
+    // Bilinearly filtered lookup with normalized coordinates, clamped to the edges
+    pub fn sample(&self, u: f32, v: f32) -> Vec4 {
+        let x = (u * self.width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+
+        let x0 = x as usize;
+        let y0 = y as usize;
+        let x1 = (x0 + 1).min(self.width - 1);
+        let y1 = (y0 + 1).min(self.height - 1);
+        let xd = x - x0 as f32;
+        let yd = y - y0 as f32;
+
+        let t = self.get_pixel(x0, y0).lerp(self.get_pixel(x1, y0), xd);
+        let b = self.get_pixel(x0, y1).lerp(self.get_pixel(x1, y1), xd);
+        t.lerp(b, yd)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, Vec4> {
+        self.data.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> core::slice::IterMut<'_, Vec4> {
+        self.data.iter_mut()
+    }
+}
@@ -0,0 +1,340 @@
+use crate::glam::*;
+use crate::resources::{Image, ImageData, TexelFormat};
+
+use std::f32::consts::PI;
+
+pub fn direction_to_equirect(direction: Vec3) -> Vec2 {
+    let direction = direction.normalize();
+    let u = direction.x.atan2(-direction.z) / (2.0 * PI) + 0.5;
+    let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+    Vec2::new(u, v)
+}
+
+pub fn equirect_to_direction(uv: Vec2) -> Vec3 {
+    let phi = (uv.x - 0.5) * 2.0 * PI;
+    let theta = uv.y * PI;
+    Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+}
+
+// Bilinear lookup that wraps horizontally and clamps vertically
+pub fn sample_equirect(image: &Image, uv: Vec2) -> Vec3 {
+    let width = image.dimensions.x;
+    let height = image.dimensions.y;
+
+    let x = uv.x * width as f32 - 0.5;
+    let y = (uv.y * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let xd = x - x0;
+    let yd = y - y0;
+
+    let x0 = (x0 as i32).rem_euclid(width) as usize;
+    let x1 = (x0 + 1) % width as usize;
+    let y0 = y0 as usize;
+    let y1 = (y0 + 1).min(height as usize - 1);
+
+    let t = image.texel(x0, y0).lerp(image.texel(x1, y0), xd);
+    let b = image.texel(x0, y1).lerp(image.texel(x1, y1), xd);
+    t.lerp(b, yd).xyz()
+}
+
+fn hammersley(i: u32, count: u32) -> Vec2 {
+    Vec2::new(i as f32 / count as f32, i.reverse_bits() as f32 / 4294967296.0)
+}
+
+fn importance_sample_ggx(xi: Vec2, n: Vec3, roughness: f32) -> Vec3 {
+    let a = roughness * roughness;
+
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = ((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let h = Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta);
+
+    let up = if n.z.abs() < 0.999 { Vec3::Z } else { Vec3::X };
+    let tangent = up.cross(n).normalize();
+    let bitangent = n.cross(tangent);
+    (tangent * h.x + bitangent * h.y + n * h.z).normalize()
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let denom = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * denom * denom)
+}
+
+fn geometry_schlick_ggx_ibl(n_dot_v: f32, roughness: f32) -> f32 {
+    let k = (roughness * roughness) / 2.0;
+    n_dot_v / (n_dot_v * (1.0 - k) + k)
+}
+
+fn downsample(image: &Image) -> Image {
+    let width = (image.dimensions.x / 2).max(1);
+    let height = (image.dimensions.y / 2).max(1);
+
+    let mut data = Vec::with_capacity((width * height * 3) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let uv = Vec2::new((x as f32 + 0.5) / width as f32, (y as f32 + 0.5) / height as f32);
+            data.extend_from_slice(&sample_equirect(image, uv).to_array());
+        }
+    }
+
+    Image::new(ImageData::F32(data), IVec2::new(width, height), TexelFormat::RGB32F)
+}
+
+fn resample(image: &Image, width: i32, height: i32) -> Image {
+    let mut image = image.clone();
+    while image.dimensions.x >= width * 2 && image.dimensions.y >= height * 2 {
+        image = downsample(&image);
+    }
+
+    let mut data = Vec::with_capacity((width * height * 3) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let uv = Vec2::new((x as f32 + 0.5) / width as f32, (y as f32 + 0.5) / height as f32);
+            data.extend_from_slice(&sample_equirect(&image, uv).to_array());
+        }
+    }
+
+    Image::new(ImageData::F32(data), IVec2::new(width, height), TexelFormat::RGB32F)
+}
+
+// Precomputed image based lighting from an equirectangular environment map:
+// diffuse irradiance as 3rd order spherical harmonics and a GGX prefiltered specular chain
+// that is combined with a split-sum BRDF lookup table.
+pub struct Environment {
+    pub irradiance: [Vec3; 9],
+    pub specular: Vec<Image>,
+    pub brdf_lut: Image,
+    pub intensity: f32
+}
+
+impl Environment {
+    pub fn from_equirect(image: &Image) -> Self {
+        Environment {
+            irradiance: Self::project_irradiance(image),
+            specular: Self::prefilter_specular(image, 256, 6, 64),
+            brdf_lut: Self::integrate_brdf(32, 128),
+            intensity: 1.0
+        }
+    }
+
+    fn sh_basis(n: Vec3) -> [f32; 9] {
+        [
+            0.282095,
+            0.488603 * n.y,
+            0.488603 * n.z,
+            0.488603 * n.x,
+            1.092548 * n.x * n.y,
+            1.092548 * n.y * n.z,
+            0.315392 * (3.0 * n.z * n.z - 1.0),
+            1.092548 * n.x * n.z,
+            0.546274 * (n.x * n.x - n.y * n.y)
+        ]
+    }
+
+    fn project_irradiance(image: &Image) -> [Vec3; 9] {
+        let image = resample(image, 128, 64);
+        let width = image.dimensions.x;
+        let height = image.dimensions.y;
+
+        let mut coefficients = [Vec3::ZERO; 9];
+        for y in 0..height {
+            for x in 0..width {
+                let uv = Vec2::new((x as f32 + 0.5) / width as f32, (y as f32 + 0.5) / height as f32);
+                let direction = equirect_to_direction(uv);
+                let solid_angle = (2.0 * PI / width as f32) * (PI / height as f32) * (uv.y * PI).sin();
+
+                let radiance = image.texel(x as usize, y as usize).xyz();
+                for (coefficient, basis) in coefficients.iter_mut().zip(Self::sh_basis(direction)) {
+                    *coefficient += radiance * basis * solid_angle;
+                }
+            }
+        }
+
+        // Convolve with the clamped cosine lobe and fold in the lambertian 1/pi
+        let bands = [1.0, 2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 0.25, 0.25, 0.25, 0.25, 0.25];
+        for (coefficient, band) in coefficients.iter_mut().zip(bands) {
+            *coefficient *= band;
+        }
+        coefficients
+    }
+
+    fn prefilter_specular(image: &Image, width: i32, levels: usize, sample_count: u32) -> Vec<Image> {
+        let mut pyramid = vec![resample(image, width, width / 2)];
+        while pyramid.last().unwrap().dimensions.y > 1 {
+            let next = downsample(pyramid.last().unwrap());
+            pyramid.push(next);
+        }
+
+        let mut specular = vec![pyramid[0].clone()];
+        for level in 1..levels {
+            let roughness = level as f32 / (levels - 1) as f32;
+            let level_width = (width >> level).max(8);
+            let level_height = (level_width / 2).max(4);
+
+            let mut data = Vec::with_capacity((level_width * level_height * 3) as usize);
+            for y in 0..level_height {
+                for x in 0..level_width {
+                    let uv = Vec2::new((x as f32 + 0.5) / level_width as f32, (y as f32 + 0.5) / level_height as f32);
+                    let n = equirect_to_direction(uv);
+
+                    let mut color = Vec3::ZERO;
+                    let mut weight = 0.0;
+                    for i in 0..sample_count {
+                        let h = importance_sample_ggx(hammersley(i, sample_count), n, roughness);
+                        let l = (2.0 * n.dot(h) * h - n).normalize();
+                        let n_dot_l = n.dot(l);
+                        if n_dot_l > 0.0 {
+                            // Sample a blurrier source level for unlikely directions to reduce noise
+                            let n_dot_h = n.dot(h).max(0.0);
+                            let pdf = distribution_ggx(n_dot_h, roughness) * 0.25 + 0.0001;
+                            let texel_solid_angle = 4.0 * PI / (width * width / 2) as f32;
+                            let sample_solid_angle = 1.0 / (sample_count as f32 * pdf);
+                            let lod = (0.5 * (sample_solid_angle / texel_solid_angle).log2() + 1.0).clamp(0.0, (pyramid.len() - 1) as f32);
+
+                            color += sample_equirect(&pyramid[lod.round() as usize], direction_to_equirect(l)) * n_dot_l;
+                            weight += n_dot_l;
+                        }
+                    }
+
+                    data.extend_from_slice(&(color / weight.max(0.0001)).to_array());
+                }
+            }
+
+            specular.push(Image::new(ImageData::F32(data), IVec2::new(level_width, level_height), TexelFormat::RGB32F));
+        }
+
+        specular
+    }
+
+    fn integrate_brdf(size: i32, sample_count: u32) -> Image {
+        let mut data = Vec::with_capacity((size * size * 2) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                let n_dot_v = ((x as f32 + 0.5) / size as f32).max(0.001);
+                let roughness = (y as f32 + 0.5) / size as f32;
+
+                let v = Vec3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+                let n = Vec3::Z;
+
+                let mut a = 0.0;
+                let mut b = 0.0;
+                for i in 0..sample_count {
+                    let h = importance_sample_ggx(hammersley(i, sample_count), n, roughness);
+                    let l = (2.0 * v.dot(h) * h - v).normalize();
+
+                    let n_dot_l = l.z.max(0.0);
+                    let n_dot_h = h.z.max(0.0);
+                    let v_dot_h = v.dot(h).max(0.0);
+                    if n_dot_l > 0.0 {
+                        let g = geometry_schlick_ggx_ibl(n_dot_v, roughness) * geometry_schlick_ggx_ibl(n_dot_l, roughness);
+                        let g_vis = (g * v_dot_h) / (n_dot_h * n_dot_v);
+                        let fc = (1.0 - v_dot_h).powf(5.0);
+
+                        a += (1.0 - fc) * g_vis;
+                        b += fc * g_vis;
+                    }
+                }
+
+                data.push(a / sample_count as f32);
+                data.push(b / sample_count as f32);
+            }
+        }
+
+        Image::new(ImageData::F32(data), IVec2::new(size, size), TexelFormat::RG32F)
+    }
+
+    // Irradiance divided by pi, multiply with the albedo for the diffuse contribution
+    pub fn diffuse(&self, n: Vec3) -> Vec3 {
+        let n = n.normalize();
+        let mut irradiance = Vec3::ZERO;
+        for (coefficient, basis) in self.irradiance.iter().zip(Self::sh_basis(n)) {
+            irradiance += *coefficient * basis;
+        }
+        irradiance.max(Vec3::ZERO) * self.intensity
+    }
+
+    // Prefiltered radiance around the reflection vector, interpolated between roughness levels
+    pub fn specular(&self, r: Vec3, roughness: f32) -> Vec3 {
+        let level = roughness.clamp(0.0, 1.0) * (self.specular.len() - 1) as f32;
+        self.sample(r, level)
+    }
+
+    pub fn sample(&self, direction: Vec3, level: f32) -> Vec3 {
+        let uv = direction_to_equirect(direction);
+        let level = level.clamp(0.0, (self.specular.len() - 1) as f32);
+        let level0 = level.floor() as usize;
+        let level1 = (level0 + 1).min(self.specular.len() - 1);
+
+        let a = sample_equirect(&self.specular[level0], uv);
+        let b = sample_equirect(&self.specular[level1], uv);
+        a.lerp(b, level - level0 as f32) * self.intensity
+    }
+
+    // Split-sum scale and bias applied to F0
+    pub fn brdf(&self, n_dot_v: f32, roughness: f32) -> Vec2 {
+        let size = self.brdf_lut.dimensions.x as f32;
+        let x = (n_dot_v * size - 0.5).clamp(0.0, size - 1.0);
+        let y = (roughness * size - 0.5).clamp(0.0, size - 1.0);
+        let x0 = x as usize;
+        let y0 = y as usize;
+        let x1 = (x0 + 1).min(size as usize - 1);
+        let y1 = (y0 + 1).min(size as usize - 1);
+
+        let t = self.brdf_lut.texel(x0, y0).lerp(self.brdf_lut.texel(x1, y0), x - x0 as f32);
+        let b = self.brdf_lut.texel(x0, y1).lerp(self.brdf_lut.texel(x1, y1), x - x0 as f32);
+        let value = t.lerp(b, y - y0 as f32);
+        Vec2::new(value.x, value.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constant_environment(radiance: Vec3) -> Image {
+        let data = (0..32 * 16).flat_map(|_| radiance.to_array()).collect();
+        Image::new(ImageData::F32(data), IVec2::new(32, 16), TexelFormat::RGB32F)
+    }
+
+    #[test]
+    fn equirect_round_trip() {
+        for direction in [Vec3::X, Vec3::NEG_Z, Vec3::new(0.3, 0.8, -0.5), Vec3::new(-0.6, -0.2, 0.7)] {
+            let direction = direction.normalize();
+            let uv = direction_to_equirect(direction);
+            assert!(equirect_to_direction(uv).distance(direction) < 1e-5, "{:?}", direction);
+        }
+    }
+
+    #[test]
+    fn constant_environment_projects_to_the_first_band() {
+        let radiance = Vec3::new(0.5, 1.0, 2.0);
+        let irradiance = Environment::project_irradiance(&constant_environment(radiance));
+
+        // The integral of the constant basis function over the sphere is 4pi * 0.282095
+        assert!((irradiance[0] - radiance * 4.0 * PI * 0.282095).abs().max_element() < 1e-2);
+        for coefficient in &irradiance[1..] {
+            assert!(coefficient.abs().max_element() < 1e-2, "{:?}", coefficient);
+        }
+    }
+
+    #[test]
+    fn constant_environment_lights_evenly() {
+        let radiance = Vec3::new(0.5, 1.0, 2.0);
+        let environment = Environment::from_equirect(&constant_environment(radiance));
+
+        for n in [Vec3::Y, Vec3::NEG_Y, Vec3::X, Vec3::new(1.0, 1.0, -1.0)] {
+            assert!((environment.diffuse(n) - radiance).abs().max_element() < 2e-2, "{:?}", environment.diffuse(n));
+            for roughness in [0.0, 0.5, 1.0] {
+                assert!((environment.specular(n, roughness) - radiance).abs().max_element() < 1e-3);
+            }
+        }
+
+        for (n_dot_v, roughness) in [(0.1, 0.1), (0.5, 0.5), (1.0, 0.9)] {
+            let brdf = environment.brdf(n_dot_v, roughness);
+            assert!(brdf.min_element() >= 0.0 && brdf.x + brdf.y <= 1.0 + 1e-3, "{:?}", brdf);
+        }
+    }
+}
@@ -5,4 +5,25 @@ pub mod pipeline;
 pub use pipeline::Pipeline;
 
 pub mod shader;
-pub use shader::*;
\ No newline at end of file
+pub use shader::*;
+
+pub mod color_buffer;
+pub use color_buffer::ColorBuffer;
+
+pub mod tone_mapping;
+pub use tone_mapping::*;
+
+pub mod post_processing;
+pub use post_processing::*;
+
+pub mod post_effects;
+pub use post_effects::*;
+
+pub mod shadow_map;
+pub use shadow_map::*;
+
+pub mod environment;
+pub use environment::*;
+
+pub mod background;
+pub use background::*;
\ No newline at end of file
@@ -1,17 +1,8 @@
 use crate::glam::*;
 use crate::window::FrameBuffer;
-use crate::resources::Vertex;
+use crate::resources::{Vertex, PrimitiveType};
 use crate::resources::Material;
-use crate::graphics::{Shader, ShaderIn};
-
-fn from_vec3_rgb(rgb: &Vec3) -> u32 {
-    from_u8_rgb((rgb.x * 255.99) as u8, (rgb.y * 255.99) as u8, (rgb.z * 255.99) as u8)
-}
-
-fn from_u8_rgb(r: u8, g: u8, b: u8) -> u32 {
-    let (r, g, b) = (r as u32, g as u32, b as u32);
-    (r << 16) | (g << 8) | b
-}
+use crate::graphics::{Shader, ShaderIn, ColorBuffer, Background};
 
 pub struct Pipeline {
     model_matrix: Mat4,
@@ -47,43 +38,140 @@ impl Pipeline {
         self.depth_buffer.clear(u32::MAX);
     }
 
-    pub fn draw_vertices(&mut self, shader: &dyn Shader, material: &Material, frame_buffer: &mut FrameBuffer, vertices: &Vec<Vertex>) {
-        self.adapt_depth_buffer(&frame_buffer);
+    pub fn draw_vertices(&mut self, shader: &dyn Shader, material: &Material, color_buffer: &mut ColorBuffer, vertices: &[Vertex]) {
+        self.adapt_depth_buffer(color_buffer);
 
         let mvp =  self.proj_matrix * self.view_matrix * self.model_matrix;
+        let mirrored = self.model_matrix.determinant() < 0.0;
         let inv_trans_model_matrix = self.model_matrix.inverse().transpose();
 
         let triangle_count = vertices.len() / 3;
         for i in 0..triangle_count {
             let v0 = &vertices[(i * 3 + 0) as usize];
-            let v1 = &vertices[(i * 3 + 1) as usize];
-            let v2 = &vertices[(i * 3 + 2) as usize];
+            let (v1, v2) = Self::winding(mirrored, &vertices[(i * 3 + 1) as usize], &vertices[(i * 3 + 2) as usize]);
 
             let a = Self::project(&v0.position, &mvp);
             let b = Self::project(&v1.position, &mvp);
             let c = Self::project(&v2.position, &mvp);
 
-            Self::draw_triangle(shader, material, frame_buffer, &mut self.depth_buffer, &a, &b, &c, v0, v1, v2, &self.model_matrix, &inv_trans_model_matrix);
+            Self::draw_triangle(shader, material, color_buffer, &mut self.depth_buffer, &a, &b, &c, v0, v1, v2, &self.model_matrix, &inv_trans_model_matrix);
         }
     }
 
-    pub fn draw_vertices_indexed(&mut self, shader: &dyn Shader, material: &Material, frame_buffer: &mut FrameBuffer, vertices: &Vec<Vertex>, indices: &Vec<u32>) {
-        self.adapt_depth_buffer(&frame_buffer);
+    pub fn draw_vertices_indexed(&mut self, shader: &dyn Shader, material: &Material, color_buffer: &mut ColorBuffer, vertices: &[Vertex], indices: &[u32]) {
+        self.adapt_depth_buffer(color_buffer);
 
         let mvp =  self.proj_matrix * self.view_matrix * self.model_matrix;
+        let mirrored = self.model_matrix.determinant() < 0.0;
         let inv_trans_model_matrix = self.model_matrix.inverse().transpose();
 
         let triangle_count = indices.len() / 3;
         for i in 0..triangle_count {
             let v0 = &vertices[indices[(i * 3 + 0) as usize] as usize];
-            let v1 = &vertices[indices[(i * 3 + 1) as usize] as usize];
-            let v2 = &vertices[indices[(i * 3 + 2) as usize] as usize];
+            let (v1, v2) = Self::winding(mirrored, &vertices[indices[(i * 3 + 1) as usize] as usize], &vertices[indices[(i * 3 + 2) as usize] as usize]);
+
+            let a = Self::project(&v0.position, &mvp);
+            let b = Self::project(&v1.position, &mvp);
+            let c = Self::project(&v2.position, &mvp);
+
+            Self::draw_triangle(shader, material, color_buffer, &mut self.depth_buffer, &a, &b, &c, v0, v1, v2, &self.model_matrix, &inv_trans_model_matrix);
+        }
+    }
+
+    // Every pair of indices is a segment, walked one pixel at a time along its major axis
+    pub fn draw_lines_indexed(&mut self, shader: &dyn Shader, material: &Material, color_buffer: &mut ColorBuffer, vertices: &[Vertex], indices: &[u32]) {
+        self.adapt_depth_buffer(color_buffer);
+
+        let mvp =  self.proj_matrix * self.view_matrix * self.model_matrix;
+        let inv_trans_model_matrix = self.model_matrix.inverse().transpose();
+        let screen_size = Vec2::new(color_buffer.width() as f32, color_buffer.height() as f32);
+
+        let line_count = indices.len() / 2;
+        for i in 0..line_count {
+            let v0 = &vertices[indices[i * 2 + 0] as usize];
+            let v1 = &vertices[indices[i * 2 + 1] as usize];
+
+            let a = Self::project(&v0.position, &mvp);
+            let b = Self::project(&v1.position, &mvp);
+            // Segments crossing the camera plane are not clipped
+            if a.1 <= 0.0 || b.1 <= 0.0 {
+                continue;
+            }
+
+            let pa = Self::clip_to_screen_space(Vec2::new(a.0.x, a.0.y), screen_size);
+            let pb = Self::clip_to_screen_space(Vec2::new(b.0.x, b.0.y), screen_size);
+            let steps = (pb - pa).abs().max_element().ceil().max(1.0) as usize;
+
+            for step in 0..=steps {
+                let t = step as f32 / steps as f32;
+                let p = pa.lerp(pb, t);
+                if p.x < 0.0 || p.y < 0.0 || p.x >= screen_size.x || p.y >= screen_size.y {
+                    continue;
+                }
+
+                let (x, y) = (p.x as usize, p.y as usize);
+                let z = a.0.z + (b.0.z - a.0.z) * t;
+                if z < self.depth_buffer.get_pixel_f32(x, y) {
+                    self.depth_buffer.set_pixel_f32(x, y, z);
+
+                    let shader_in = ShaderIn {
+                        primitive_type: PrimitiveType::Lines,
+                        ..Self::interpolate(v0, v1, v1, Vec3::new(1.0 - t, t, 0.0), Vec3::new(a.1, b.1, b.1), &self.model_matrix, &inv_trans_model_matrix)
+                    };
+                    color_buffer.set_pixel(x, y, shader.shade(material, &shader_in));
+                }
+            }
+        }
+    }
+
+    // Points cover the single pixel they project to
+    pub fn draw_points_indexed(&mut self, shader: &dyn Shader, material: &Material, color_buffer: &mut ColorBuffer, vertices: &[Vertex], indices: &[u32]) {
+        self.adapt_depth_buffer(color_buffer);
+
+        let mvp =  self.proj_matrix * self.view_matrix * self.model_matrix;
+        let inv_trans_model_matrix = self.model_matrix.inverse().transpose();
+        let screen_size = Vec2::new(color_buffer.width() as f32, color_buffer.height() as f32);
+
+        for index in indices {
+            let v = &vertices[*index as usize];
+            let a = Self::project(&v.position, &mvp);
+            if a.1 <= 0.0 {
+                continue;
+            }
+
+            let p = Self::clip_to_screen_space(Vec2::new(a.0.x, a.0.y), screen_size);
+            if p.x < 0.0 || p.y < 0.0 || p.x >= screen_size.x || p.y >= screen_size.y {
+                continue;
+            }
+
+            let (x, y) = (p.x as usize, p.y as usize);
+            if a.0.z < self.depth_buffer.get_pixel_f32(x, y) {
+                self.depth_buffer.set_pixel_f32(x, y, a.0.z);
+
+                let shader_in = ShaderIn {
+                    primitive_type: PrimitiveType::Points,
+                    ..Self::interpolate(v, v, v, Vec3::X, Vec3::splat(a.1), &self.model_matrix, &inv_trans_model_matrix)
+                };
+                color_buffer.set_pixel(x, y, shader.shade(material, &shader_in));
+            }
+        }
+    }
+
+    // Depth only rasterization into the given target, used for shadow maps
+    pub fn draw_depth_indexed(&mut self, depth_buffer: &mut FrameBuffer, vertices: &[Vertex], indices: &[u32]) {
+        let mvp =  self.proj_matrix * self.view_matrix * self.model_matrix;
+        let mirrored = self.model_matrix.determinant() < 0.0;
+
+        let triangle_count = indices.len() / 3;
+        for i in 0..triangle_count {
+            let v0 = &vertices[indices[i * 3 + 0] as usize];
+            let (v1, v2) = Self::winding(mirrored, &vertices[indices[i * 3 + 1] as usize], &vertices[indices[i * 3 + 2] as usize]);
 
             let a = Self::project(&v0.position, &mvp);
             let b = Self::project(&v1.position, &mvp);
             let c = Self::project(&v2.position, &mvp);
 
-            Self::draw_triangle(shader, material, frame_buffer, &mut self.depth_buffer, &a, &b, &c, v0, v1, v2, &self.model_matrix, &inv_trans_model_matrix);
+            Self::draw_depth_triangle(depth_buffer, &a.0, &b.0, &c.0);
         }
     }
 
@@ -91,13 +179,21 @@ impl Pipeline {
         (clip_space * -0.5 + 0.5) * screen_size
     }
 
-    fn adapt_depth_buffer(&mut self, frame_buffer: &FrameBuffer) {
-        if self.depth_buffer.width() != frame_buffer.width() || self.depth_buffer.height() != frame_buffer.height() {
-            self.depth_buffer = FrameBuffer::new(frame_buffer.width(), frame_buffer.height());
+    // Draws the background behind everything rendered so far, using the current view and projection
+    pub fn draw_background(&mut self, background: &Background, color_buffer: &mut ColorBuffer) {
+        self.adapt_depth_buffer(color_buffer);
+
+        let inv_view_proj = (self.proj_matrix * self.view_matrix).inverse();
+        background.render(color_buffer, &self.depth_buffer, &inv_view_proj);
+    }
+
+    fn adapt_depth_buffer(&mut self, color_buffer: &ColorBuffer) {
+        if self.depth_buffer.width() != color_buffer.width() || self.depth_buffer.height() != color_buffer.height() {
+            self.depth_buffer = FrameBuffer::new(color_buffer.width(), color_buffer.height());
         }
     }
 
-    fn draw_triangle(shader: &dyn Shader, material: &Material, frame_buffer: &mut FrameBuffer, depth_buffer: &mut FrameBuffer, a: &(Vec3, f32), b: &(Vec3, f32), c: &(Vec3, f32), v0: &Vertex, v1: &Vertex, v2: &Vertex, model_matrix: &Mat4, inv_trans_model_matrix: &Mat4) {
+    fn draw_triangle(shader: &dyn Shader, material: &Material, color_buffer: &mut ColorBuffer, depth_buffer: &mut FrameBuffer, a: &(Vec3, f32), b: &(Vec3, f32), c: &(Vec3, f32), v0: &Vertex, v1: &Vertex, v2: &Vertex, model_matrix: &Mat4, inv_trans_model_matrix: &Mat4) {
         let rec0 = a.1;
         let rec1 = b.1;
         let rec2 = c.1;
@@ -110,12 +206,12 @@ impl Pipeline {
         let z1 = b.z;
         let z2 = c.z;
 
-        let a = Self::clip_to_screen_space(Vec2::new(a.x, a.y), Vec2::new(frame_buffer.width() as f32, frame_buffer.height() as f32));
-        let b = Self::clip_to_screen_space(Vec2::new(b.x, b.y), Vec2::new(frame_buffer.width() as f32, frame_buffer.height() as f32));
-        let c = Self::clip_to_screen_space(Vec2::new(c.x, c.y), Vec2::new(frame_buffer.width() as f32, frame_buffer.height() as f32));
+        let a = Self::clip_to_screen_space(Vec2::new(a.x, a.y), Vec2::new(color_buffer.width() as f32, color_buffer.height() as f32));
+        let b = Self::clip_to_screen_space(Vec2::new(b.x, b.y), Vec2::new(color_buffer.width() as f32, color_buffer.height() as f32));
+        let c = Self::clip_to_screen_space(Vec2::new(c.x, c.y), Vec2::new(color_buffer.width() as f32, color_buffer.height() as f32));
 
         let min = a.min(b.min(c)).max(Vec2::new(0.0, 0.0));
-        let max = (a.max(b.max(c)) + 1.0).min(Vec2::new(frame_buffer.width() as f32, frame_buffer.height() as f32));
+        let max = (a.max(b.max(c)) + 1.0).min(Vec2::new(color_buffer.width() as f32, color_buffer.height() as f32));
 
         for x in (min.x as usize)..(max.x as usize) {
             for y in (min.y as usize)..(max.y as usize) {
@@ -132,7 +228,7 @@ impl Pipeline {
                 let edge2 = b - a;
                 overlaps &= if a0 == 0.0 { (edge0.y == 0.0 && edge0.x > 0.0) ||  edge0.y > 0.0 } else { a0 > 0.0 };
                 overlaps &= if a1 == 0.0 { (edge1.y == 0.0 && edge1.x > 0.0) ||  edge1.y > 0.0 }  else { a1 > 0.0 };
-                overlaps &= if a1 == 0.0 { (edge2.y == 0.0 && edge2.x > 0.0) ||  edge2.y > 0.0 }  else { a2 > 0.0 };
+                overlaps &= if a2 == 0.0 { (edge2.y == 0.0 && edge2.x > 0.0) ||  edge2.y > 0.0 }  else { a2 > 0.0 };
 
                 if overlaps {
                     let area_rep = 1.0 / Self::edge_function(&a, &b, &c);
@@ -143,36 +239,93 @@ impl Pipeline {
                     if z < d {
                         depth_buffer.set_pixel_f32(x, y, z);
 
-                        let mv0 = *model_matrix * Vec4::from((v0.position * rec0, 1.0));
-                        let mv1 = *model_matrix * Vec4::from((v1.position * rec1, 1.0));
-                        let mv2 = *model_matrix * Vec4::from((v2.position * rec2, 1.0));
+                        let shader_in = Self::interpolate(v0, v1, v2, bary, Vec3::new(rec0, rec1, rec2), model_matrix, inv_trans_model_matrix);
+                        let color = shader.shade(material, &shader_in);
+                        color_buffer.set_pixel(x, y, color);
+                    }
+                }
+            }
+        }
+    }
 
-                        let n0 = *inv_trans_model_matrix * Vec4::from((v0.normal, 1.0));
-                        let n1 = *inv_trans_model_matrix * Vec4::from((v1.normal, 1.0));
-                        let n2 = *inv_trans_model_matrix * Vec4::from((v2.normal, 1.0));
+    fn draw_depth_triangle(depth_buffer: &mut FrameBuffer, a: &Vec3, b: &Vec3, c: &Vec3) {
+        let z0 = a.z;
+        let z1 = b.z;
+        let z2 = c.z;
 
-                        let correction = 1.0 / (bary.x * rec0 + bary.y * rec1 + bary.z * rec2);
+        let screen_size = Vec2::new(depth_buffer.width() as f32, depth_buffer.height() as f32);
+        let a = Self::clip_to_screen_space(Vec2::new(a.x, a.y), screen_size);
+        let b = Self::clip_to_screen_space(Vec2::new(b.x, b.y), screen_size);
+        let c = Self::clip_to_screen_space(Vec2::new(c.x, c.y), screen_size);
 
-                        let position = mv0 * bary.x + mv1 * bary.y + mv2 * bary.z;
-                        // let normal = v0.normal * rec0 * bary.x + v1.normal * rec1 * bary.y + v2.normal * rec2 * bary.z;
-                        let normal = n0 * rec0 * bary.x + n1 * rec1 * bary.y + n2 * rec2 * bary.z;
-                        let tex_coord = v0.tex_coord * rec0 * bary.x + v1.tex_coord * rec1 * bary.y + v2.tex_coord * rec2 * bary.z;
+        let area = Self::edge_function(&a, &b, &c);
+        if area <= 0.0 {
+            return;
+        }
+        let area_rep = 1.0 / area;
 
-                        let shader_in = ShaderIn {
-                            position: Vec3::new(position.x, position.y, position.z) * correction,
-                            normal: Vec3::new(normal.x, normal.y, normal.z) * correction,
-                            tex_coord: tex_coord * correction
-                        };
+        let min = a.min(b.min(c)).max(Vec2::new(0.0, 0.0));
+        let max = (a.max(b.max(c)) + 1.0).min(screen_size);
 
-                        let color = shader.shade(material, &shader_in);
-                        let color = Vec3::new(color.x, color.y, color.z);
-                        frame_buffer.set_pixel(x, y, from_vec3_rgb(&color));
+        for x in (min.x as usize)..(max.x as usize) {
+            for y in (min.y as usize)..(max.y as usize) {
+                let p = Vec2::new(x as f32, y as f32) + 0.5;
+
+                let a0 = Self::edge_function(&b, &c, &p);
+                let a1 = Self::edge_function(&c, &a, &p);
+                let a2 = Self::edge_function(&a, &b, &p);
+
+                if a0 >= 0.0 && a1 >= 0.0 && a2 >= 0.0 {
+                    let z = (z0 * a0 + z1 * a1 + z2 * a2) * area_rep;
+                    if z < depth_buffer.get_pixel_f32(x, y) {
+                        depth_buffer.set_pixel_f32(x, y, z);
                     }
                 }
             }
         }
     }
 
+    // Perspective correct interpolation of the vertex attributes with screen space barycentrics
+    fn interpolate(v0: &Vertex, v1: &Vertex, v2: &Vertex, bary: Vec3, rec: Vec3, model_matrix: &Mat4, inv_trans_model_matrix: &Mat4) -> ShaderIn {
+        let (rec0, rec1, rec2) = (rec.x, rec.y, rec.z);
+
+        let mv0 = *model_matrix * Vec4::from((v0.position, 1.0)) * rec0;
+        let mv1 = *model_matrix * Vec4::from((v1.position, 1.0)) * rec1;
+        let mv2 = *model_matrix * Vec4::from((v2.position, 1.0)) * rec2;
+
+        let n0 = *inv_trans_model_matrix * Vec4::from((v0.normal, 0.0));
+        let n1 = *inv_trans_model_matrix * Vec4::from((v1.normal, 0.0));
+        let n2 = *inv_trans_model_matrix * Vec4::from((v2.normal, 0.0));
+
+        let t0 = Vec4::from((model_matrix.transform_vector3(v0.tangent.xyz()), v0.tangent.w));
+        let t1 = Vec4::from((model_matrix.transform_vector3(v1.tangent.xyz()), v1.tangent.w));
+        let t2 = Vec4::from((model_matrix.transform_vector3(v2.tangent.xyz()), v2.tangent.w));
+
+        let correction = 1.0 / (bary.x * rec0 + bary.y * rec1 + bary.z * rec2);
+
+        let position = mv0 * bary.x + mv1 * bary.y + mv2 * bary.z;
+        let normal = n0 * rec0 * bary.x + n1 * rec1 * bary.y + n2 * rec2 * bary.z;
+        let tangent = t0 * rec0 * bary.x + t1 * rec1 * bary.y + t2 * rec2 * bary.z;
+        let tex_coord = v0.tex_coord * rec0 * bary.x + v1.tex_coord * rec1 * bary.y + v2.tex_coord * rec2 * bary.z;
+        let tex_coord_1 = v0.tex_coord_1 * rec0 * bary.x + v1.tex_coord_1 * rec1 * bary.y + v2.tex_coord_1 * rec2 * bary.z;
+        let color = v0.color * rec0 * bary.x + v1.color * rec1 * bary.y + v2.color * rec2 * bary.z;
+
+        ShaderIn {
+            position: Vec3::new(position.x, position.y, position.z) * correction,
+            normal: Vec3::new(normal.x, normal.y, normal.z) * correction,
+            tangent: tangent * correction,
+            tex_coord: tex_coord * correction,
+            tex_coord_1: tex_coord_1 * correction,
+            color: color * correction,
+            primitive_type: PrimitiveType::Triangles
+        }
+    }
+
+    // Mirroring transforms flip the winding order, swap two vertices to keep front faces visible
+    fn winding<'a>(mirrored: bool, v1: &'a Vertex, v2: &'a Vertex) -> (&'a Vertex, &'a Vertex) {
+        if mirrored { (v2, v1) } else { (v1, v2) }
+    }
+
     fn project(p: &Vec3, mvp: &Mat4) -> (Vec3, f32) {
         let proj_pos = *mvp * Vec4::from((*p, 1.0));
         let rec = 1.0 / proj_pos.w;
@@ -183,4 +336,72 @@ impl Pipeline {
     fn edge_function(a: &Vec2, c: &Vec2, b: &Vec2) -> f32 {
         (c.x - a.x) * (b.y - a.y) - (c.y - a.y) * (b.x - a.x)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Outputs the interpolated world position
+    struct PositionShader;
+
+    impl Shader for PositionShader {
+        fn shade(&self, _material: &Material, inputs: &ShaderIn) -> Vec4 {
+            Vec4::from((inputs.position, 1.0))
+        }
+    }
+
+    // Full screen quad split along the diagonal that passes through the pixel centers
+    fn quad(z: f32) -> Vec<Vertex> {
+        [Vec2::new(-1.0, -1.0), Vec2::new(1.0, -1.0), Vec2::new(1.0, 1.0), Vec2::new(-1.0, 1.0)].iter().map(|corner| Vertex {
+            position: Vec3::new(corner.x * -z, corner.y * -z, z),
+            ..Vertex::default()
+        }).collect()
+    }
+
+    fn render(pipeline: &mut Pipeline, vertices: &[Vertex], indices: &[u32]) -> ColorBuffer {
+        let mut color_buffer = ColorBuffer::new(4, 4);
+        // Sizes the depth buffer to the target, so it can be cleared
+        pipeline.draw_vertices_indexed(&PositionShader, &Material::default(), &mut color_buffer, &[], &[]);
+        pipeline.clear_depth();
+        pipeline.draw_vertices_indexed(&PositionShader, &Material::default(), &mut color_buffer, vertices, indices);
+        color_buffer
+    }
+
+    #[test]
+    fn shared_edges_leave_no_gaps() {
+        let mut pipeline = Pipeline::new();
+        pipeline.set_proj_matrix(Mat4::perspective_rh((90.0f32).to_radians(), 1.0, 0.1, 10.0));
+
+        // Every vertex order, so the shared edge is tested as each of the three edges
+        let first = [0, 1, 2];
+        let second = [0, 2, 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                let indices = [first[i], first[(i + 1) % 3], first[(i + 2) % 3], second[j], second[(j + 1) % 3], second[(j + 2) % 3]];
+                let color_buffer = render(&mut pipeline, &quad(-2.0), &indices);
+                assert!(color_buffer.iter().all(|color| color.w == 1.0), "{:?}", indices);
+            }
+        }
+    }
+
+    #[test]
+    fn positions_are_perspective_correct() {
+        let mut pipeline = Pipeline::new();
+        pipeline.set_proj_matrix(Mat4::perspective_rh((90.0f32).to_radians(), 1.0, 0.1, 10.0));
+        pipeline.set_model_matrix(Mat4::from_translation(Vec3::new(0.0, 0.0, -1.0)));
+
+        // Tilted quad, its depth varies across the pixels it covers
+        let mut vertices = quad(-2.0);
+        vertices[0].position *= 2.0;
+        vertices[3].position *= 2.0;
+        let color_buffer = render(&mut pipeline, &vertices, &[0, 1, 2, 0, 2, 3]);
+
+        // All interpolated positions lie on the plane of the quad, in world space
+        let normal = (vertices[1].position - vertices[0].position).cross(vertices[2].position - vertices[0].position).normalize();
+        let origin = vertices[1].position + Vec3::new(0.0, 0.0, -1.0);
+        assert!(color_buffer.iter().filter(|color| color.w == 1.0).count() > 4);
+        for color in color_buffer.iter().filter(|color| color.w == 1.0) {
+            assert!((color.xyz() - origin).dot(normal).abs() < 1e-4, "{:?}", color);
+        }
+    }
+}
@@ -0,0 +1,470 @@
+use crate::glam::*;
+use crate::graphics::{ColorBuffer, PostEffect};
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Perceptual luma of the tone compressed color, so edge detection behaves on HDR input
+fn luma(color: Vec4) -> f32 {
+    let color = color.xyz().max(Vec3::ZERO);
+    let color = color / (color + 1.0);
+    color.dot(Vec3::new(0.299, 0.587, 0.114)).sqrt()
+}
+
+// PCG hash
+fn hash(value: u32) -> u32 {
+    let state = value.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+pub struct Fxaa {
+    pub contrast_threshold: f32,
+    pub relative_threshold: f32,
+    pub subpixel_blending: f32,
+    pub max_search_steps: usize,
+
+    source: ColorBuffer,
+    luma: Vec<f32>
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Fxaa {
+            contrast_threshold: 0.0312,
+            relative_threshold: 0.063,
+            subpixel_blending: 0.75,
+            max_search_steps: 10,
+            source: ColorBuffer::new(0, 0),
+            luma: Vec::new()
+        }
+    }
+}
+
+impl Fxaa {
+    fn luma_at(&self, x: i32, y: i32) -> f32 {
+        let x = x.clamp(0, self.source.width() as i32 - 1) as usize;
+        let y = y.clamp(0, self.source.height() as i32 - 1) as usize;
+        self.luma[y * self.source.width() + x]
+    }
+
+    // Bilinear luma lookup in pixel space, pixel centers are at integer coordinates
+    fn sample_luma(&self, p: Vec2) -> f32 {
+        let x0 = p.x.floor();
+        let y0 = p.y.floor();
+        let xd = p.x - x0;
+        let yd = p.y - y0;
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let t = self.luma_at(x0, y0) * (1.0 - xd) + self.luma_at(x0 + 1, y0) * xd;
+        let b = self.luma_at(x0, y0 + 1) * (1.0 - xd) + self.luma_at(x0 + 1, y0 + 1) * xd;
+        t * (1.0 - yd) + b * yd
+    }
+}
+
+impl PostEffect for Fxaa {
+    fn name(&self) -> &str {
+        "fxaa"
+    }
+
+    fn apply(&mut self, color_buffer: &mut ColorBuffer) {
+        self.source.clone_from(color_buffer);
+        self.luma.clear();
+        self.luma.extend(self.source.iter().map(|color| luma(*color)));
+
+        let width = color_buffer.width();
+        let height = color_buffer.height();
+        for y in 0..height {
+            for x in 0..width {
+                let (xi, yi) = (x as i32, y as i32);
+                let m = self.luma_at(xi, yi);
+                let n = self.luma_at(xi, yi - 1);
+                let s = self.luma_at(xi, yi + 1);
+                let e = self.luma_at(xi + 1, yi);
+                let w = self.luma_at(xi - 1, yi);
+
+                let highest = m.max(n).max(s).max(e).max(w);
+                let lowest = m.min(n).min(s).min(e).min(w);
+                let contrast = highest - lowest;
+                if contrast < self.contrast_threshold.max(self.relative_threshold * highest) {
+                    continue;
+                }
+
+                let ne = self.luma_at(xi + 1, yi - 1);
+                let nw = self.luma_at(xi - 1, yi - 1);
+                let se = self.luma_at(xi + 1, yi + 1);
+                let sw = self.luma_at(xi - 1, yi + 1);
+
+                let filter = (2.0 * (n + e + s + w) + ne + nw + se + sw) / 12.0;
+                let filter = ((filter - m).abs() / contrast).clamp(0.0, 1.0);
+                let subpixel_blend = smoothstep(0.0, 1.0, filter);
+                let subpixel_blend = subpixel_blend * subpixel_blend * self.subpixel_blending;
+
+                let horizontal = (n + s - 2.0 * m).abs() * 2.0 + (ne + se - 2.0 * e).abs() + (nw + sw - 2.0 * w).abs();
+                let vertical = (e + w - 2.0 * m).abs() * 2.0 + (ne + nw - 2.0 * n).abs() + (se + sw - 2.0 * s).abs();
+                let is_horizontal = horizontal >= vertical;
+
+                // Step across the edge, towards the neighbour with the steepest gradient
+                let (positive, negative) = if is_horizontal { (s, n) } else { (e, w) };
+                let positive_gradient = (positive - m).abs();
+                let negative_gradient = (negative - m).abs();
+                let (step, opposite, gradient) = if positive_gradient < negative_gradient {
+                    (-1.0, negative, negative_gradient)
+                } else {
+                    (1.0, positive, positive_gradient)
+                };
+
+                let across = if is_horizontal { Vec2::new(0.0, step) } else { Vec2::new(step, 0.0) };
+                let along = if is_horizontal { Vec2::new(1.0, 0.0) } else { Vec2::new(0.0, 1.0) };
+
+                let center = Vec2::new(x as f32, y as f32);
+                let edge = center + across * 0.5;
+                let edge_luma = (m + opposite) * 0.5;
+                let gradient_threshold = gradient * 0.25;
+
+                let mut positive_distance = self.max_search_steps as f32;
+                let mut positive_delta = 0.0;
+                for i in 1..=self.max_search_steps {
+                    positive_delta = self.sample_luma(edge + along * i as f32) - edge_luma;
+                    if positive_delta.abs() >= gradient_threshold {
+                        positive_distance = i as f32;
+                        break;
+                    }
+                }
+
+                let mut negative_distance = self.max_search_steps as f32;
+                let mut negative_delta = 0.0;
+                for i in 1..=self.max_search_steps {
+                    negative_delta = self.sample_luma(edge - along * i as f32) - edge_luma;
+                    if negative_delta.abs() >= gradient_threshold {
+                        negative_distance = i as f32;
+                        break;
+                    }
+                }
+
+                let (shortest, delta) = if positive_distance <= negative_distance {
+                    (positive_distance, positive_delta)
+                } else {
+                    (negative_distance, negative_delta)
+                };
+
+                let edge_blend = if (delta >= 0.0) == (m - edge_luma >= 0.0) {
+                    0.0
+                } else {
+                    0.5 - shortest / (positive_distance + negative_distance)
+                };
+
+                let blend = subpixel_blend.max(edge_blend);
+                let p = center + 0.5 + across * blend;
+                color_buffer.set_pixel(x, y, self.source.sample(p.x / width as f32, p.y / height as f32));
+            }
+        }
+    }
+}
+
+pub struct Bloom {
+    pub threshold: f32,
+    pub knee: f32,
+    pub intensity: f32,
+    pub levels: usize,
+
+    mips: Vec<ColorBuffer>,
+    scratch: Vec<ColorBuffer>
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.5,
+            levels: 5,
+            mips: Vec::new(),
+            scratch: Vec::new()
+        }
+    }
+}
+
+impl Bloom {
+    fn prefilter(&self, color: Vec4) -> Vec4 {
+        let brightness = color.x.max(color.y).max(color.z);
+        let soft = (brightness - self.threshold + self.knee).clamp(0.0, 2.0 * self.knee);
+        let soft = soft * soft / (4.0 * self.knee + 0.00001);
+        let contribution = soft.max(brightness - self.threshold) / brightness.max(0.00001);
+        color * contribution
+    }
+
+    fn downsample(source: &ColorBuffer, target: &mut ColorBuffer) {
+        for y in 0..target.height() {
+            for x in 0..target.width() {
+                let u = (x as f32 + 0.5) / target.width() as f32;
+                let v = (y as f32 + 0.5) / target.height() as f32;
+                target.set_pixel(x, y, source.sample(u, v));
+            }
+        }
+    }
+
+    // Separable 5-tap binomial blur
+    fn blur(buffer: &mut ColorBuffer, scratch: &mut ColorBuffer) {
+        const WEIGHTS: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+
+        let width = buffer.width() as i32;
+        let height = buffer.height() as i32;
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Vec4::ZERO;
+                for (i, weight) in WEIGHTS.iter().enumerate() {
+                    let sx = (x + i as i32 - 2).clamp(0, width - 1);
+                    sum += buffer.get_pixel(sx as usize, y as usize) * *weight;
+                }
+                scratch.set_pixel(x as usize, y as usize, sum);
+            }
+        }
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Vec4::ZERO;
+                for (i, weight) in WEIGHTS.iter().enumerate() {
+                    let sy = (y + i as i32 - 2).clamp(0, height - 1);
+                    sum += scratch.get_pixel(x as usize, sy as usize) * *weight;
+                }
+                buffer.set_pixel(x as usize, y as usize, sum);
+            }
+        }
+    }
+}
+
+impl PostEffect for Bloom {
+    fn name(&self) -> &str {
+        "bloom"
+    }
+
+    fn apply(&mut self, color_buffer: &mut ColorBuffer) {
+        let mut width = color_buffer.width() / 2;
+        let mut height = color_buffer.height() / 2;
+        let mut levels = 0;
+        while levels < self.levels && width > 0 && height > 0 {
+            if self.mips.len() <= levels {
+                self.mips.push(ColorBuffer::new(0, 0));
+                self.scratch.push(ColorBuffer::new(0, 0));
+            }
+            self.mips[levels].resize(width, height);
+            self.scratch[levels].resize(width, height);
+
+            width /= 2;
+            height /= 2;
+            levels += 1;
+        }
+
+        if levels == 0 {
+            return;
+        }
+
+        for y in 0..self.mips[0].height() {
+            for x in 0..self.mips[0].width() {
+                let u = (x as f32 + 0.5) / self.mips[0].width() as f32;
+                let v = (y as f32 + 0.5) / self.mips[0].height() as f32;
+                let color = self.prefilter(color_buffer.sample(u, v));
+                self.mips[0].set_pixel(x, y, color);
+            }
+        }
+
+        for i in 1..levels {
+            let (source, target) = self.mips.split_at_mut(i);
+            Self::downsample(&source[i - 1], &mut target[0]);
+        }
+
+        for i in 0..levels {
+            Self::blur(&mut self.mips[i], &mut self.scratch[i]);
+        }
+
+        // Accumulate the chain back up, from the smallest level to the largest
+        for i in (1..levels).rev() {
+            let (target, source) = self.mips.split_at_mut(i);
+            let target = &mut target[i - 1];
+            let source = &source[0];
+            for y in 0..target.height() {
+                for x in 0..target.width() {
+                    let u = (x as f32 + 0.5) / target.width() as f32;
+                    let v = (y as f32 + 0.5) / target.height() as f32;
+                    let color = target.get_pixel(x, y) + source.sample(u, v);
+                    target.set_pixel(x, y, color);
+                }
+            }
+        }
+
+        let scale = self.intensity / levels as f32;
+        for y in 0..color_buffer.height() {
+            for x in 0..color_buffer.width() {
+                let u = (x as f32 + 0.5) / color_buffer.width() as f32;
+                let v = (y as f32 + 0.5) / color_buffer.height() as f32;
+                let bloom = self.mips[0].sample(u, v) * scale;
+                let color = color_buffer.get_pixel(x, y) + Vec4::from((bloom.xyz(), 0.0));
+                color_buffer.set_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+pub struct Vignette {
+    pub intensity: f32,
+    pub radius: f32,
+    pub smoothness: f32
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Vignette {
+            intensity: 0.5,
+            radius: 0.9,
+            smoothness: 0.6
+        }
+    }
+}
+
+impl PostEffect for Vignette {
+    fn name(&self) -> &str {
+        "vignette"
+    }
+
+    fn apply(&mut self, color_buffer: &mut ColorBuffer) {
+        let width = color_buffer.width();
+        let height = color_buffer.height();
+        for y in 0..height {
+            for x in 0..width {
+                let uv = Vec2::new((x as f32 + 0.5) / width as f32, (y as f32 + 0.5) / height as f32);
+                // 0 in the center and 1 in the corners
+                let distance = (uv - 0.5).length() * std::f32::consts::SQRT_2;
+                let falloff = smoothstep(self.radius - self.smoothness, self.radius, distance);
+
+                let color = color_buffer.get_pixel(x, y);
+                let factor = 1.0 - self.intensity * falloff;
+                color_buffer.set_pixel(x, y, Vec4::from((color.xyz() * factor, color.w)));
+            }
+        }
+    }
+}
+
+pub struct ChromaticAberration {
+    // Channel offset in normalized coordinates at the corners of the screen
+    pub strength: f32,
+
+    source: ColorBuffer
+}
+
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        ChromaticAberration {
+            strength: 0.004,
+            source: ColorBuffer::new(0, 0)
+        }
+    }
+}
+
+impl PostEffect for ChromaticAberration {
+    fn name(&self) -> &str {
+        "chromatic_aberration"
+    }
+
+    fn apply(&mut self, color_buffer: &mut ColorBuffer) {
+        self.source.clone_from(color_buffer);
+
+        let width = color_buffer.width();
+        let height = color_buffer.height();
+        for y in 0..height {
+            for x in 0..width {
+                let uv = Vec2::new((x as f32 + 0.5) / width as f32, (y as f32 + 0.5) / height as f32);
+                let offset = (uv - 0.5) * 2.0 * self.strength;
+
+                let r = self.source.sample(uv.x + offset.x, uv.y + offset.y).x;
+                let center = self.source.get_pixel(x, y);
+                let b = self.source.sample(uv.x - offset.x, uv.y - offset.y).z;
+
+                color_buffer.set_pixel(x, y, Vec4::new(r, center.y, b, center.w));
+            }
+        }
+    }
+}
+
+pub struct FilmGrain {
+    pub intensity: f32,
+    // How much the grain is suppressed in bright areas, 0 applies it evenly
+    pub response: f32,
+
+    frame: u32
+}
+
+impl Default for FilmGrain {
+    fn default() -> Self {
+        FilmGrain {
+            intensity: 0.08,
+            response: 0.8,
+            frame: 0
+        }
+    }
+}
+
+impl PostEffect for FilmGrain {
+    fn name(&self) -> &str {
+        "film_grain"
+    }
+
+    fn apply(&mut self, color_buffer: &mut ColorBuffer) {
+        self.frame = self.frame.wrapping_add(1);
+
+        let seed = hash(self.frame);
+        for (i, color) in color_buffer.iter_mut().enumerate() {
+            let noise = hash(seed ^ hash(i as u32)) as f32 / u32::MAX as f32 * 2.0 - 1.0;
+
+            let luminance = color.xyz().dot(Vec3::new(0.2126, 0.7152, 0.0722)).clamp(0.0, 1.0);
+            let weight = self.intensity * (1.0 - self.response * luminance);
+
+            let grain = 1.0 + noise * weight;
+            *color = Vec4::from((color.xyz() * grain, color.w));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uniform_buffer(color: Vec4) -> ColorBuffer {
+        let mut color_buffer = ColorBuffer::new(16, 16);
+        color_buffer.clear(color);
+        color_buffer
+    }
+
+    #[test]
+    fn uniform_images_are_left_alone() {
+        let color = Vec4::new(0.25, 0.5, 0.75, 1.0);
+
+        let mut color_buffer = uniform_buffer(color);
+        Fxaa::default().apply(&mut color_buffer);
+        assert!(color_buffer.iter().all(|pixel| (*pixel - color).abs().max_element() < 1e-6));
+
+        let mut color_buffer = uniform_buffer(color);
+        ChromaticAberration::default().apply(&mut color_buffer);
+        assert!(color_buffer.iter().all(|pixel| (*pixel - color).abs().max_element() < 1e-6));
+    }
+
+    #[test]
+    fn vignette_darkens_the_corners_only() {
+        let mut color_buffer = uniform_buffer(Vec4::ONE);
+        Vignette::default().apply(&mut color_buffer);
+
+        assert_eq!(color_buffer.get_pixel(8, 8), Vec4::ONE);
+        assert!(color_buffer.get_pixel(0, 0).x < 0.75);
+        assert_eq!(color_buffer.get_pixel(0, 0).w, 1.0);
+    }
+
+    #[test]
+    fn bloom_spreads_bright_pixels() {
+        let mut color_buffer = uniform_buffer(Vec4::ZERO);
+        color_buffer.set_pixel(8, 8, Vec4::new(100.0, 100.0, 100.0, 1.0));
+        Bloom::default().apply(&mut color_buffer);
+
+        assert!(color_buffer.get_pixel(10, 8).x > 0.0);
+    }
+}
@@ -0,0 +1,177 @@
+use std::any::Any;
+
+use crate::graphics::ColorBuffer;
+
+pub trait PostEffect: Any {
+    fn name(&self) -> &str;
+    fn apply(&mut self, color_buffer: &mut ColorBuffer);
+}
+
+struct PostEffectEntry {
+    enabled: bool,
+    effect: Box<dyn PostEffect>
+}
+
+// Ordered chain of effects that runs on the linear HDR color buffer before tone mapping
+pub struct PostProcessStack {
+    effects: Vec<PostEffectEntry>
+}
+
+impl Default for PostProcessStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostProcessStack {
+    pub fn new() -> Self {
+        PostProcessStack {
+            effects: Vec::new()
+        }
+    }
+
+    pub fn push<T: PostEffect>(&mut self, effect: T) -> usize {
+        self.effects.push(PostEffectEntry {
+            enabled: true,
+            effect: Box::new(effect)
+        });
+        self.effects.len() - 1
+    }
+
+    // Added to the chain but left off until toggled, for optional stylistic effects
+    pub fn push_disabled<T: PostEffect>(&mut self, effect: T) -> usize {
+        let index = self.push(effect);
+        self.effects[index].enabled = false;
+        index
+    }
+
+    pub fn insert<T: PostEffect>(&mut self, index: usize, effect: T) {
+        self.effects.insert(index, PostEffectEntry {
+            enabled: true,
+            effect: Box::new(effect)
+        });
+    }
+
+    pub fn remove(&mut self, index: usize) -> Box<dyn PostEffect> {
+        self.effects.remove(index).effect
+    }
+
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.effects.iter().position(|entry| entry.effect.name() == name)
+    }
+
+    pub fn name(&self, index: usize) -> &str {
+        self.effects[index].effect.name()
+    }
+
+    pub fn is_enabled(&self, index: usize) -> bool {
+        self.effects[index].enabled
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        self.effects[index].enabled = enabled;
+    }
+
+    pub fn toggle(&mut self, index: usize) -> bool {
+        self.effects[index].enabled = !self.effects[index].enabled;
+        self.effects[index].enabled
+    }
+
+    pub fn get<T: PostEffect>(&self) -> Option<&T> {
+        self.effects.iter().find_map(|entry| {
+            let effect: &dyn Any = entry.effect.as_ref();
+            effect.downcast_ref::<T>()
+        })
+    }
+
+    pub fn get_mut<T: PostEffect>(&mut self) -> Option<&mut T> {
+        self.effects.iter_mut().find_map(|entry| {
+            let effect: &mut dyn Any = entry.effect.as_mut();
+            effect.downcast_mut::<T>()
+        })
+    }
+
+    pub fn apply(&mut self, color_buffer: &mut ColorBuffer) {
+        for entry in &mut self.effects {
+            if entry.enabled {
+                entry.effect.apply(color_buffer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glam::*;
+
+    struct Add(f32);
+
+    impl PostEffect for Add {
+        fn name(&self) -> &str {
+            "add"
+        }
+
+        fn apply(&mut self, color_buffer: &mut ColorBuffer) {
+            for color in color_buffer.iter_mut() {
+                *color += Vec4::splat(self.0);
+            }
+        }
+    }
+
+    struct Scale(f32);
+
+    impl PostEffect for Scale {
+        fn name(&self) -> &str {
+            "scale"
+        }
+
+        fn apply(&mut self, color_buffer: &mut ColorBuffer) {
+            for color in color_buffer.iter_mut() {
+                *color *= self.0;
+            }
+        }
+    }
+
+    #[test]
+    fn effects_run_in_order_when_enabled() {
+        let mut post_processing = PostProcessStack::new();
+        post_processing.push(Add(1.0));
+        let scale = post_processing.push_disabled(Scale(3.0));
+        assert!(!post_processing.is_enabled(scale));
+
+        let mut color_buffer = ColorBuffer::new(2, 2);
+        post_processing.apply(&mut color_buffer);
+        assert_eq!(color_buffer.get_pixel(1, 1), Vec4::splat(1.0));
+
+        assert!(post_processing.toggle(scale));
+        color_buffer.clear(Vec4::ZERO);
+        post_processing.apply(&mut color_buffer);
+        assert_eq!(color_buffer.get_pixel(1, 1), Vec4::splat(3.0));
+    }
+
+    #[test]
+    fn effects_are_found_by_name_and_type() {
+        let mut post_processing = PostProcessStack::new();
+        post_processing.push(Add(1.0));
+        post_processing.push(Scale(2.0));
+
+        assert_eq!(post_processing.find("scale"), Some(1));
+        assert_eq!(post_processing.find("missing"), None);
+
+        post_processing.get_mut::<Scale>().unwrap().0 = 4.0;
+        assert_eq!(post_processing.get::<Scale>().unwrap().0, 4.0);
+
+        post_processing.remove(0);
+        assert_eq!(post_processing.name(0), "scale");
+        assert!(post_processing.get::<Add>().is_none());
+    }
+}
@@ -1,10 +1,15 @@
 use crate::glam::*;
-use crate::resources::Material;
+use crate::resources::{Material, PrimitiveType};
 
 pub struct ShaderIn {
     pub position: Vec3,
     pub normal: Vec3,
-    pub tex_coord: Vec2
+    // World space tangent, w holds the handedness of the bitangent
+    pub tangent: Vec4,
+    pub tex_coord: Vec2,
+    pub tex_coord_1: Vec2,
+    pub color: Vec4,
+    pub primitive_type: PrimitiveType
 }
 
 pub trait Shader {
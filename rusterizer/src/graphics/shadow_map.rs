@@ -0,0 +1,317 @@
+use crate::glam::*;
+use crate::window::FrameBuffer;
+use crate::graphics::Pipeline;
+use crate::resources::Vertex;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShadowFilter {
+    Hard,
+    // Percentage-closer filtering over a fixed kernel, radius in texels
+    Pcf { radius: f32 },
+    // Percentage-closer soft shadows, the penumbra grows with the blocker to receiver distance
+    Pcss { light_angle: f32, blocker_search_radius: f32, max_radius: f32 }
+}
+
+impl ShadowFilter {
+    pub fn next(&self) -> Self {
+        match self {
+            ShadowFilter::Hard => ShadowFilter::Pcf { radius: 1.5 },
+            ShadowFilter::Pcf { .. } => ShadowFilter::Pcss { light_angle: 0.02, blocker_search_radius: 4.0, max_radius: 8.0 },
+            ShadowFilter::Pcss { .. } => ShadowFilter::Hard
+        }
+    }
+}
+
+pub struct ShadowCascade {
+    pub depth_buffer: FrameBuffer,
+    pub view_matrix: Mat4,
+    pub proj_matrix: Mat4,
+    pub view_proj: Mat4,
+    // View space distance range of the camera frustum slice covered by this cascade
+    pub split_near: f32,
+    pub split_far: f32,
+
+    texel_size: f32,
+    depth_range: f32
+}
+
+// Cascaded shadow map for a single directional light
+pub struct ShadowMap {
+    pub light_direction: Vec3,
+    pub resolution: usize,
+    pub max_distance: f32,
+    // Blend between uniform (0) and logarithmic (1) cascade splits
+    pub split_lambda: f32,
+    // How far behind a cascade casters are still captured, in world units
+    pub caster_extension: f32,
+    pub depth_bias: f32,
+    // Receiver offset along the normal, in shadow map texels
+    pub normal_bias: f32,
+    pub filter: ShadowFilter,
+    pub cascades: Vec<ShadowCascade>,
+
+    // Camera the cascades were last fitted to, receivers pick their cascade by depth in its view space
+    camera_view: Mat4
+}
+
+fn pcf_offsets() -> impl Iterator<Item = Vec2> {
+    (0..5).flat_map(|y| (0..5).map(move |x| Vec2::new(x as f32 - 2.0, y as f32 - 2.0) * 0.5))
+}
+
+impl ShadowMap {
+    pub fn new(resolution: usize, cascade_count: usize) -> Self {
+        let cascades = (0..cascade_count).map(|_| ShadowCascade {
+            depth_buffer: FrameBuffer::new(resolution, resolution),
+            view_matrix: Mat4::IDENTITY,
+            proj_matrix: Mat4::IDENTITY,
+            view_proj: Mat4::IDENTITY,
+            split_near: 0.0,
+            split_far: 0.0,
+            texel_size: 0.0,
+            depth_range: 0.0
+        }).collect();
+
+        ShadowMap {
+            light_direction: Vec3::new(0.1, -1.0, 0.0).normalize(),
+            resolution: resolution,
+            max_distance: 20.0,
+            split_lambda: 0.75,
+            caster_extension: 10.0,
+            depth_bias: 0.002,
+            normal_bias: 1.5,
+            filter: ShadowFilter::Pcf { radius: 1.5 },
+            cascades: cascades,
+            camera_view: Mat4::IDENTITY
+        }
+    }
+
+    // The camera projection has to be symmetric, either perspective or orthographic
+    pub fn fit_cascades(&mut self, camera_view: &Mat4, camera_proj: &Mat4, near: f32, far: f32) {
+        let far = far.min(self.max_distance);
+        self.camera_view = *camera_view;
+        let cascade_count = self.cascades.len();
+        let inv_camera_view = camera_view.inverse();
+        let light_direction = self.light_direction.normalize();
+        let up = if light_direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+
+        for (i, cascade) in self.cascades.iter_mut().enumerate() {
+            let split = |i: usize| {
+                let t = i as f32 / cascade_count as f32;
+                let uniform = near + (far - near) * t;
+                let logarithmic = near * (far / near).powf(t);
+                uniform + (logarithmic - uniform) * self.split_lambda
+            };
+            let split_near = split(i);
+            let split_far = split(i + 1);
+
+            let mut corners = [Vec3::ZERO; 8];
+            for (j, z) in [split_near, split_far].iter().enumerate() {
+                // Clip w at this depth is z for perspective and 1 for orthographic projections
+                let clip_w = camera_proj.z_axis.w * -z + camera_proj.w_axis.w;
+                let h = clip_w / camera_proj.y_axis.y;
+                let w = clip_w / camera_proj.x_axis.x;
+                corners[j * 4 + 0] = inv_camera_view.transform_point3(Vec3::new(-w, -h, -z));
+                corners[j * 4 + 1] = inv_camera_view.transform_point3(Vec3::new(w, -h, -z));
+                corners[j * 4 + 2] = inv_camera_view.transform_point3(Vec3::new(w, h, -z));
+                corners[j * 4 + 3] = inv_camera_view.transform_point3(Vec3::new(-w, h, -z));
+            }
+
+            // A bounding sphere keeps the projection size constant while the camera rotates
+            let center = corners.iter().fold(Vec3::ZERO, |sum, corner| sum + *corner) / 8.0;
+            let radius = corners.iter().fold(0.0f32, |radius, corner| radius.max(corner.distance(center)));
+            let radius = (radius * 16.0).ceil() / 16.0;
+            let texel_size = radius * 2.0 / self.resolution as f32;
+
+            // Snap to whole texels to avoid shimmering edges when the camera moves
+            let rotation = Mat4::look_at_rh(Vec3::ZERO, light_direction, up);
+            let mut light_center = rotation.transform_point3(center);
+            light_center.x = (light_center.x / texel_size).floor() * texel_size;
+            light_center.y = (light_center.y / texel_size).floor() * texel_size;
+            let center = rotation.inverse().transform_point3(light_center);
+
+            let depth_range = radius * 2.0 + self.caster_extension;
+            let eye = center - light_direction * (radius + self.caster_extension);
+            cascade.view_matrix = Mat4::look_at_rh(eye, center, up);
+            cascade.proj_matrix = Mat4::orthographic_rh(-radius, radius, -radius, radius, 0.0, depth_range);
+            cascade.view_proj = cascade.proj_matrix * cascade.view_matrix;
+            cascade.split_near = split_near;
+            cascade.split_far = split_far;
+            cascade.texel_size = texel_size;
+            cascade.depth_range = depth_range;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for cascade in &mut self.cascades {
+            if cascade.depth_buffer.width() != self.resolution {
+                cascade.depth_buffer = FrameBuffer::new(self.resolution, self.resolution);
+            }
+            cascade.depth_buffer.clear(u32::MAX);
+        }
+    }
+
+    // Renders the mesh into every cascade, this overrides the matrices of the pipeline
+    pub fn render(&mut self, pipeline: &mut Pipeline, model_matrix: &Mat4, vertices: &[Vertex], indices: &[u32]) {
+        pipeline.set_model_matrix(*model_matrix);
+        for cascade in &mut self.cascades {
+            pipeline.set_view_matrix(cascade.view_matrix);
+            pipeline.set_proj_matrix(cascade.proj_matrix);
+            pipeline.draw_depth_indexed(&mut cascade.depth_buffer, vertices, indices);
+        }
+    }
+
+    // Returns 1 for fully lit and 0 for fully shadowed
+    pub fn shadow_factor(&self, position: Vec3, normal: Vec3) -> f32 {
+        let view_depth = -self.camera_view.transform_point3(position).z;
+        if let Some(cascade) = self.cascades.iter().find(|cascade| (cascade.split_near..=cascade.split_far).contains(&view_depth)) {
+            let position = position + normal * self.normal_bias * cascade.texel_size;
+            let clip = cascade.view_proj * Vec4::from((position, 1.0));
+            if clip.x.abs() > 1.0 || clip.y.abs() > 1.0 || clip.z < 0.0 || clip.z > 1.0 {
+                return 1.0;
+            }
+
+            let uv = (Vec2::new(clip.x, clip.y) * -0.5 + 0.5) * self.resolution as f32;
+            let depth = clip.z - self.depth_bias;
+
+            return match self.filter {
+                ShadowFilter::Hard => self.compare(cascade, uv, depth),
+                ShadowFilter::Pcf { radius } => self.pcf(cascade, uv, depth, radius),
+                ShadowFilter::Pcss { light_angle, blocker_search_radius, max_radius } => {
+                    let mut blocker_depth = 0.0;
+                    let mut blocker_count = 0;
+                    for offset in pcf_offsets() {
+                        let sample = self.depth(cascade, uv + offset * blocker_search_radius);
+                        if sample < depth {
+                            blocker_depth += sample;
+                            blocker_count += 1;
+                        }
+                    }
+
+                    if blocker_count == 0 {
+                        1.0
+                    } else {
+                        let blocker_depth = blocker_depth / blocker_count as f32;
+                        let distance = (depth - blocker_depth) * cascade.depth_range;
+                        let penumbra = distance * light_angle.tan() / cascade.texel_size;
+                        self.pcf(cascade, uv, depth, penumbra.clamp(0.5, max_radius))
+                    }
+                }
+            };
+        }
+
+        1.0
+    }
+
+    fn depth(&self, cascade: &ShadowCascade, uv: Vec2) -> f32 {
+        let max = self.resolution as f32 - 1.0;
+        let x = uv.x.clamp(0.0, max) as usize;
+        let y = uv.y.clamp(0.0, max) as usize;
+        cascade.depth_buffer.get_pixel_f32(x, y)
+    }
+
+    fn compare(&self, cascade: &ShadowCascade, uv: Vec2, depth: f32) -> f32 {
+        if depth > self.depth(cascade, uv) { 0.0 } else { 1.0 }
+    }
+
+    fn pcf(&self, cascade: &ShadowCascade, uv: Vec2, depth: f32, radius: f32) -> f32 {
+        let mut lit = 0.0;
+        let mut count = 0.0;
+        for offset in pcf_offsets() {
+            lit += self.compare(cascade, uv + offset * radius, depth);
+            count += 1.0;
+        }
+        lit / count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perspective() -> Mat4 {
+        Mat4::perspective_rh((60.0f32).to_radians(), 1.0, 0.1, 100.0)
+    }
+
+    fn fitted_shadow_map(camera_view: &Mat4, camera_proj: &Mat4) -> ShadowMap {
+        let mut shadow_map = ShadowMap::new(256, 3);
+        shadow_map.light_direction = Vec3::NEG_Y;
+        shadow_map.fit_cascades(camera_view, camera_proj, 0.1, 100.0);
+        shadow_map
+    }
+
+    fn assert_contains(cascade: &ShadowCascade, camera_view: &Mat4, corners: [Vec3; 4]) {
+        for corner in corners {
+            let position = camera_view.inverse().transform_point3(corner);
+            let clip = cascade.view_proj.project_point3(position);
+            assert!(clip.x.abs() <= 1.0 && clip.y.abs() <= 1.0, "{:?}", clip);
+            assert!((0.0..=1.0).contains(&clip.z), "{:?}", clip);
+        }
+    }
+
+    #[test]
+    fn cascade_splits_cover_the_view_range() {
+        let mut shadow_map = fitted_shadow_map(&Mat4::IDENTITY, &perspective());
+        assert_eq!(shadow_map.cascades[0].split_near, 0.1);
+        assert!((shadow_map.cascades[2].split_far - shadow_map.max_distance).abs() < 1e-4);
+        for pair in shadow_map.cascades.windows(2) {
+            assert_eq!(pair[0].split_far, pair[1].split_near);
+            assert!(pair[0].split_far - pair[0].split_near < pair[1].split_far - pair[1].split_near);
+        }
+
+        shadow_map.split_lambda = 0.0;
+        shadow_map.fit_cascades(&Mat4::IDENTITY, &perspective(), 0.1, 100.0);
+        for cascade in &shadow_map.cascades {
+            assert!((cascade.split_far - cascade.split_near - (20.0 - 0.1) / 3.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn cascades_contain_their_frustum_slice() {
+        let camera_view = Mat4::look_at_rh(Vec3::new(3.0, 4.0, 5.0), Vec3::ZERO, Vec3::Y);
+        let shadow_map = fitted_shadow_map(&camera_view, &perspective());
+        let tan_half_fov = (30.0f32).to_radians().tan();
+
+        for cascade in &shadow_map.cascades {
+            for z in [cascade.split_near, cascade.split_far] {
+                let h = z * tan_half_fov;
+                assert_contains(cascade, &camera_view, [Vec3::new(-h, -h, -z), Vec3::new(h, -h, -z), Vec3::new(h, h, -z), Vec3::new(-h, h, -z)]);
+            }
+        }
+    }
+
+    #[test]
+    fn cascades_fit_orthographic_cameras() {
+        let camera_view = Mat4::look_at_rh(Vec3::new(3.0, 4.0, 5.0), Vec3::ZERO, Vec3::Y);
+        let shadow_map = fitted_shadow_map(&camera_view, &Mat4::orthographic_rh(-4.0, 4.0, -2.0, 2.0, 0.1, 100.0));
+
+        // The slices keep the size of the view volume at every depth
+        for cascade in &shadow_map.cascades {
+            for z in [cascade.split_near, cascade.split_far] {
+                assert_contains(cascade, &camera_view, [Vec3::new(-4.0, -2.0, -z), Vec3::new(4.0, -2.0, -z), Vec3::new(4.0, 2.0, -z), Vec3::new(-4.0, 2.0, -z)]);
+            }
+        }
+    }
+
+    #[test]
+    fn occluders_cast_shadows() {
+        let camera_view = Mat4::look_at_rh(Vec3::new(0.0, 5.0, 10.0), Vec3::ZERO, Vec3::Y);
+        let mut shadow_map = fitted_shadow_map(&camera_view, &perspective());
+        shadow_map.filter = ShadowFilter::Hard;
+
+        let vertex = |x: f32, z: f32| Vertex {
+            position: Vec3::new(x, 2.0, z),
+            ..Vertex::default()
+        };
+        let vertices = [vertex(-1.0, -1.0), vertex(1.0, -1.0), vertex(1.0, 1.0), vertex(-1.0, 1.0)];
+        // Both windings, so the quad is drawn whichever way it faces the light
+        let indices = [0, 1, 2, 0, 2, 3, 0, 2, 1, 0, 3, 2];
+
+        let mut pipeline = Pipeline::new();
+        shadow_map.clear();
+        shadow_map.render(&mut pipeline, &Mat4::IDENTITY, &vertices, &indices);
+
+        assert_eq!(shadow_map.shadow_factor(Vec3::ZERO, Vec3::Y), 0.0);
+        assert_eq!(shadow_map.shadow_factor(Vec3::new(3.0, 0.0, 0.0), Vec3::Y), 1.0);
+        assert_eq!(shadow_map.shadow_factor(Vec3::new(0.0, 3.0, 0.0), Vec3::Y), 1.0);
+    }
+}
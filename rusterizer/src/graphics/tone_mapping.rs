@@ -0,0 +1,197 @@
+use crate::glam::*;
+use crate::window::FrameBuffer;
+use crate::graphics::ColorBuffer;
+use crate::color::linear_to_srgb_u8;
+
+fn from_vec3_rgb(rgb: &Vec3) -> u32 {
+    from_u8_rgb(linear_to_srgb_u8(rgb.x), linear_to_srgb_u8(rgb.y), linear_to_srgb_u8(rgb.z))
+}
+
+fn from_u8_rgb(r: u8, g: u8, b: u8) -> u32 {
+    let (r, g, b) = (r as u32, g as u32, b as u32);
+    (r << 16) | (g << 8) | b
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMappingOperator {
+    Reinhard,
+    AcesFilmic,
+    AgX,
+    Uncharted2
+}
+
+impl ToneMappingOperator {
+    pub fn next(&self) -> Self {
+        match self {
+            ToneMappingOperator::Reinhard => ToneMappingOperator::AcesFilmic,
+            ToneMappingOperator::AcesFilmic => ToneMappingOperator::AgX,
+            ToneMappingOperator::AgX => ToneMappingOperator::Uncharted2,
+            ToneMappingOperator::Uncharted2 => ToneMappingOperator::Reinhard
+        }
+    }
+}
+
+// Maps the linear HDR color target to the displayable frame buffer
+pub struct ToneMapper {
+    pub operator: ToneMappingOperator,
+    // Exposure in stops, the color is scaled by 2^exposure before tone mapping
+    pub exposure: f32
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        ToneMapper {
+            operator: ToneMappingOperator::Reinhard,
+            exposure: 0.0
+        }
+    }
+}
+
+// Stephen Hill's fit of the ACES RRT and ODT
+fn aces_filmic(color: Vec3) -> Vec3 {
+    let input = Mat3::from_cols_array(&[
+        0.59719, 0.35458, 0.04823,
+        0.07600, 0.90834, 0.01566,
+        0.02840, 0.13383, 0.83777
+    ]).transpose();
+    let output = Mat3::from_cols_array(&[
+        1.60475, -0.53108, -0.07367,
+        -0.10208, 1.10813, -0.00605,
+        -0.00327, -0.07276, 1.07602
+    ]).transpose();
+
+    let v = input * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.432951) + 0.238081;
+    (output * (a / b)).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+// Minimal AgX with the default contrast curve approximation
+fn agx(color: Vec3) -> Vec3 {
+    let inset = Mat3::from_cols_array(&[
+        0.84247905, 0.042328242, 0.042375654,
+        0.0784336, 0.87846863, 0.0784336,
+        0.079223745, 0.07916613, 0.879143
+    ]);
+    let outset = Mat3::from_cols_array(&[
+        1.196879, -0.052896854, -0.052971635,
+        -0.09802088, 1.1519032, -0.09804345,
+        -0.09902974, -0.098961174, 1.1510737
+    ]);
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    let v = inset * color.max(Vec3::splat(1e-10));
+    let v = (Vec3::new(v.x.log2(), v.y.log2(), v.z.log2()).clamp(Vec3::splat(min_ev), Vec3::splat(max_ev)) - min_ev) / (max_ev - min_ev);
+
+    let v2 = v * v;
+    let v4 = v2 * v2;
+    let v = 15.5 * v4 * v2 - 40.14 * v4 * v + 31.96 * v4 - 6.868 * v2 * v + 0.4298 * v2 + 0.1191 * v - 0.00232;
+
+    // The curve produces display encoded values, bring them back to linear for the sRGB encode
+    (outset * v).clamp(Vec3::ZERO, Vec3::ONE).powf(2.2)
+}
+
+// John Hable's filmic curve from Uncharted 2
+fn uncharted2(color: Vec3) -> Vec3 {
+    fn curve(x: Vec3) -> Vec3 {
+        let a = 0.15;
+        let b = 0.50;
+        let c = 0.10;
+        let d = 0.20;
+        let e = 0.02;
+        let f = 0.30;
+        ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+    }
+
+    let white = 11.2;
+    let exposure_bias = 2.0;
+    curve(color * exposure_bias) / curve(Vec3::splat(white))
+}
+
+impl ToneMapper {
+    pub fn map(&self, color: Vec3) -> Vec3 {
+        let color = color.max(Vec3::ZERO) * self.exposure.exp2();
+
+        match self.operator {
+            ToneMappingOperator::Reinhard => color / (color + 1.0),
+            ToneMappingOperator::AcesFilmic => aces_filmic(color),
+            ToneMappingOperator::AgX => agx(color),
+            ToneMappingOperator::Uncharted2 => uncharted2(color)
+        }
+    }
+
+    pub fn resolve(&self, color_buffer: &ColorBuffer, frame_buffer: &mut FrameBuffer) {
+        assert!(color_buffer.width() == frame_buffer.width() && color_buffer.height() == frame_buffer.height(), "Failed to resolve color buffer. (Size mismatch)");
+
+        for (pixel, color) in frame_buffer.iter_mut().zip(color_buffer.iter()) {
+            *pixel = from_vec3_rgb(&self.map(color.xyz()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMappingOperator; 4] = [
+        ToneMappingOperator::Reinhard,
+        ToneMappingOperator::AcesFilmic,
+        ToneMappingOperator::AgX,
+        ToneMappingOperator::Uncharted2
+    ];
+
+    fn tone_mapper(operator: ToneMappingOperator) -> ToneMapper {
+        ToneMapper {
+            operator: operator,
+            ..ToneMapper::default()
+        }
+    }
+
+    #[test]
+    fn black_maps_to_black() {
+        for operator in OPERATORS {
+            let mapped = tone_mapper(operator).map(Vec3::ZERO);
+            assert!(mapped.abs().max_element() < 1e-4, "{:?} {:?}", operator, mapped);
+
+            // Negative input is clamped rather than producing a negative output
+            let mapped = tone_mapper(operator).map(Vec3::splat(-1.0));
+            assert!(mapped.abs().max_element() < 1e-4, "{:?} {:?}", operator, mapped);
+        }
+    }
+
+    #[test]
+    fn bright_maps_to_white() {
+        for operator in [ToneMappingOperator::Reinhard, ToneMappingOperator::AcesFilmic, ToneMappingOperator::AgX] {
+            let mapped = tone_mapper(operator).map(Vec3::splat(1e4));
+            assert!(mapped.min_element() > 0.99 && mapped.max_element() <= 1.0, "{:?} {:?}", operator, mapped);
+        }
+
+        // The filmic curve reaches white at its white point, after the exposure bias
+        let mapped = tone_mapper(ToneMappingOperator::Uncharted2).map(Vec3::splat(11.2 / 2.0));
+        assert!((mapped - Vec3::ONE).abs().max_element() < 1e-5, "{:?}", mapped);
+    }
+
+    #[test]
+    fn operators_are_monotonic() {
+        for operator in OPERATORS {
+            let tone_mapper = tone_mapper(operator);
+            let mut previous = tone_mapper.map(Vec3::ZERO);
+            for i in 0..=200 {
+                let color = Vec3::splat((i as f32 / 10.0 - 10.0).exp2());
+                let mapped = tone_mapper.map(color);
+                assert!(mapped.cmpge(previous).all(), "{:?} {:?} at {}", operator, mapped, color.x);
+                previous = mapped;
+            }
+        }
+    }
+
+    #[test]
+    fn exposure_scales_in_stops() {
+        let mut tone_mapper = tone_mapper(ToneMappingOperator::Reinhard);
+        let unexposed = tone_mapper.map(Vec3::splat(0.5));
+        tone_mapper.exposure = -1.0;
+        let exposed = tone_mapper.map(Vec3::splat(1.0));
+        assert!((exposed - unexposed).abs().max_element() < 1e-6);
+    }
+}
@@ -93,7 +93,7 @@ impl FrameBuffer {
         self.data[y * self.width + x] = value;
     }
 
-    pub fn get_pixel(&mut self, x: usize, y: usize) -> u32 {
+    pub fn get_pixel(&self, x: usize, y: usize) -> u32 {
         self.data[y * self.width + x]
     }
 
@@ -101,7 +101,7 @@ impl FrameBuffer {
         self.data[y * self.width + x] = (value * u32::MAX as f32) as u32;
     }
 
-    pub fn get_pixel_f32(&mut self, x: usize, y: usize) -> f32 {
+    pub fn get_pixel_f32(&self, x: usize, y: usize) -> f32 {
         self.data[y * self.width + x] as f32 / u32::MAX as f32
     }
 
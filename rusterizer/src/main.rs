@@ -12,16 +12,37 @@ pub use graphics::*;
 pub mod shared;
 pub use shared::Shared;
 
+pub mod color;
+
 pub mod pbr_shader;
 
 mod timer;
 use timer::Timer;
 
+fn arg_value(name: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1).cloned())
+}
+
 fn main() {
     std::env::set_var("RUST_BACKTRACE", "1");
 
     let mut resources = Resources::init();
-    let model = resources.get_model(String::from("assets/test_models/DamagedHelmet/glTF/DamagedHelmet.gltf"));
+    let model_path = arg_value("--model").unwrap_or(String::from("assets/test_models/DamagedHelmet/glTF/DamagedHelmet.gltf"));
+    let model = match resources.try_get_model(model_path) {
+        Ok(model) => model,
+        Err(error) => {
+            println!("{}", error);
+            return;
+        }
+    };
+    if let Some(scene) = arg_value("--scene").and_then(|scene| scene.parse::<usize>().ok()) {
+        model.as_mut().set_scene(scene);
+    }
+    // Index into the cameras of the active scene, the free camera is used when None
+    let mut camera: Option<usize> = None;
+    let mut skinning_method = SkinningMethod::LinearBlend;
+    let mut animation_player = AnimationPlayer::default();
 
     let mut pipeline = Pipeline::new();
     let mut cam_position = Vec3::new(0.0, -0.03, 2.8);
@@ -32,8 +53,48 @@ fn main() {
     let mut r = 0.0;
 
     let mut shader = PBRShader::default();
+    let default_lights = shader.lights.clone();
+
+    let shadow_map = Shared::new(ShadowMap::new(1024, 3));
+    shader.shadow_map = shadow_map.clone();
+
+    let mut backgrounds = vec![
+        Background::sky(shader.lights[0].direction),
+        Background::Gradient { zenith: Vec3::new(0.1, 0.2, 0.4), horizon: Vec3::new(0.5, 0.55, 0.6), ground: Vec3::new(0.1, 0.1, 0.1) },
+        Background::SolidColor(Vec3::ZERO)
+    ];
+    let environment = arg_value("--env").and_then(|environment_path| match resources.try_get_image(environment_path, None) {
+        Ok(image) => Some(image),
+        Err(error) => {
+            println!("{}", error);
+            None
+        }
+    });
+    if let Some(image) = environment {
+        shader.environment = Shared::new(Environment::from_equirect(&image.as_ref()));
+
+        match arg_value("--blur").and_then(|level| level.parse::<f32>().ok()) {
+            Some(level) => backgrounds.insert(0, Background::Environment { environment: shader.environment.clone(), level: level }),
+            None => backgrounds.insert(0, Background::Equirect { image: image, intensity: 1.0 })
+        }
+    }
+    let mut background = 0;
+
+    for warning in resources.warnings.drain(..) {
+        println!("Warning: {}", warning);
+    }
+    let mut tone_mapper = ToneMapper::default();
+
+    let mut post_processing = PostProcessStack::new();
+    post_processing.push(Bloom::default());
+    post_processing.push_disabled(ChromaticAberration::default());
+    post_processing.push_disabled(Vignette::default());
+    post_processing.push_disabled(FilmGrain::default());
+    // Last so it also smooths the edges added by the effects before it
+    post_processing.push(Fxaa::default());
 
     let mut window = Window::new(String::from("Rusterizer"), 512, 512);
+    let mut color_buffer = ColorBuffer::new(512, 512);
     while !window.should_close() {
         delta_time = delta_timer.elapsed() as f32;
         delta_timer.reset();
@@ -63,18 +124,142 @@ fn main() {
             shader.sample_bilinear = !shader.sample_bilinear;
         }
 
-        let mut frame_buffer = window.frame_buffer();
-        frame_buffer.clear(0);
+        if window.get_key(Key::F) {
+            let mut shadow_map = shadow_map.as_mut();
+            shadow_map.filter = shadow_map.filter.next();
+            println!("Shadow filter: {:?}", shadow_map.filter);
+        }
+
+        if window.get_key(Key::C) {
+            let camera_count = model.as_ref().scene_cameras().len();
+            camera = match camera {
+                None if camera_count > 0 => Some(0),
+                Some(i) if i + 1 < camera_count => Some(i + 1),
+                _ => None
+            };
+            println!("Camera: {:?}", camera);
+        }
+
+        if window.get_key(Key::K) {
+            skinning_method = skinning_method.next();
+            println!("Skinning: {:?}", skinning_method);
+        }
+
+        if window.get_key(Key::P) {
+            animation_player.playing = !animation_player.playing;
+        }
+        if window.get_key(Key::L) {
+            let animation_count = model.as_ref().animations.len();
+            if animation_count > 0 {
+                animation_player.play((animation_player.animation + 1) % animation_count);
+                println!("Animation: {}", model.as_ref().animations[animation_player.animation].name);
+            }
+        }
+
+        if window.get_key(Key::N) {
+            let mut model = model.as_mut();
+            let scene = (model.scene + 1) % model.scenes.len().max(1);
+            model.set_scene(scene);
+            camera = None;
+
+            let model_lights = model.scene_lights();
+            shader.lights = if model_lights.is_empty() { default_lights.clone() } else { model_lights };
+            println!("Scene: {}", model.scene);
+        }
+
+        if window.get_key(Key::B) {
+            background = (background + 1) % backgrounds.len();
+        }
+
+        if window.get_key(Key::T) {
+            tone_mapper.operator = tone_mapper.operator.next();
+            println!("Tone mapping: {:?}", tone_mapper.operator);
+        }
+        if window.get_key_down(Key::Equal) {
+            tone_mapper.exposure += delta_time;
+        }
+        if window.get_key_down(Key::Minus) {
+            tone_mapper.exposure -= delta_time;
+        }
+
+        let effect_keys = [Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9];
+        for (i, key) in effect_keys.iter().enumerate().take(post_processing.len()) {
+            if window.get_key(*key) {
+                post_processing.toggle(i);
+            }
+        }
+
+        let frame_buffer = window.frame_buffer();
+        color_buffer.resize(frame_buffer.width(), frame_buffer.height());
+        color_buffer.clear(Vec4::ZERO);
         pipeline.clear_depth();
 
-        shader.view_position = -cam_position;
+        animation_player.update(&mut model.as_mut(), delta_time);
+        let scene_cameras = model.as_ref().scene_cameras();
+        let (model_matrix, view_matrix, proj_matrix, near, far) = match camera.and_then(|camera| scene_cameras.get(camera)) {
+            Some(node) => {
+                let model = model.as_ref();
+                let node = &model.nodes[*node];
+                let camera = &model.cameras[node.camera.unwrap()];
+                // The view ignores the scale of the camera node
+                let (_, rotation, translation) = node.world_matrix.to_scale_rotation_translation();
+                let view_matrix = Mat4::from_rotation_translation(rotation, translation).inverse();
+                (Mat4::IDENTITY, view_matrix, camera.proj_matrix(color_buffer.aspect_ratio()), camera.z_near(), camera.z_far().min(100.0))
+            },
+            None => {
+                r += delta_time;
+                let proj_matrix = Mat4::perspective_rh((60.0f32).to_radians(), color_buffer.aspect_ratio(), 0.01, 100.0);
+                (Mat4::from_axis_angle(Vec3::Y, r), Mat4::from_translation(-cam_position), proj_matrix, 0.01, 100.0)
+            }
+        };
+        let instances = model.as_ref().instances();
+
+        shader.view_position = view_matrix.inverse().transform_point3(Vec3::ZERO);
+
+        // Lights from the file are in model space, they spin with the model
+        let model_lights = model.as_ref().scene_lights();
+        if !model_lights.is_empty() {
+            shader.lights = model_lights.iter().map(|light| light.transformed(&model_matrix)).collect();
+        }
+
+        let model = model.as_ref();
+        // Deformed once per frame, the shadow and main passes share the vertices
+        let instance_vertices: Vec<_> = instances.iter().map(|instance| model.instance_vertices(instance, skinning_method)).collect();
+
+        {
+            let mut shadow_map = shadow_map.as_mut();
+            if let Some(shadow_light) = shader.shadow_light() {
+                shadow_map.light_direction = shader.lights[shadow_light].direction;
+            }
+            shadow_map.fit_cascades(&view_matrix, &proj_matrix, near, far);
+            shadow_map.clear();
+            for (instance, vertices) in instances.iter().zip(&instance_vertices) {
+                // Lines and points don't cast shadows
+                if model.meshes[instance.mesh].primitive_type != PrimitiveType::Triangles {
+                    continue;
+                }
+                shadow_map.render(&mut pipeline, &(model_matrix * instance.world_matrix), vertices, &model.meshes[instance.mesh].indices);
+            }
+        }
+
+        pipeline.set_view_matrix(view_matrix);
+        pipeline.set_proj_matrix(proj_matrix);
+
+        for (instance, vertices) in instances.iter().zip(&instance_vertices) {
+            let mesh = &model.meshes[instance.mesh];
+            pipeline.set_model_matrix(model_matrix * instance.world_matrix);
+            let material = model.materials[mesh.material_idx].as_ref();
+            match mesh.primitive_type {
+                PrimitiveType::Triangles => pipeline.draw_vertices_indexed(&shader, &material, &mut color_buffer, vertices, &mesh.indices),
+                PrimitiveType::Lines => pipeline.draw_lines_indexed(&shader, &material, &mut color_buffer, vertices, &mesh.indices),
+                PrimitiveType::Points => pipeline.draw_points_indexed(&shader, &material, &mut color_buffer, vertices, &mesh.indices)
+            }
+        }
 
-        r += delta_time;
-        pipeline.set_model_matrix(Mat4::from_axis_angle(Vec3::Y, r) * Mat4::from_axis_angle(Vec3::X, (90.0f32).to_radians()));
-        pipeline.set_view_matrix(Mat4::from_translation(-cam_position));
-        pipeline.set_proj_matrix(Mat4::perspective_rh((60.0f32).to_radians(), frame_buffer.aspect_ratio(), 0.01, 100.0));
+        pipeline.draw_background(&backgrounds[background], &mut color_buffer);
 
-        pipeline.draw_vertices_indexed(&shader, &model.as_ref().materials[0].as_ref(), &mut frame_buffer, &model.as_ref().meshes[0].vertices, &model.as_ref().meshes[0].indices);
+        post_processing.apply(&mut color_buffer);
+        tone_mapper.resolve(&color_buffer, window.frame_buffer());
 
         window.display();
     }
@@ -1,19 +1,28 @@
-use crate::graphics::{Shader, ShaderIn};
-use crate::resources::Material;
+use crate::graphics::{Shader, ShaderIn, ShadowMap, Environment};
+use crate::resources::{Material, Light, LightKind, Texture, PrimitiveType};
 use crate::glam::*;
+use crate::Shared;
 
 use std::f32::consts::PI;
 
 pub struct PBRShader {
     pub view_position: Vec3,
-    pub sample_bilinear: bool
+    pub sample_bilinear: bool,
+
+    pub lights: Vec<Light>,
+    // Shadows are cast by the first directional light in the list
+    pub shadow_map: Shared<ShadowMap>,
+    pub environment: Shared<Environment>
 }
 
 impl Default for PBRShader {
     fn default() -> Self {
         PBRShader {
             view_position: Vec3::default(),
-            sample_bilinear: true
+            sample_bilinear: true,
+            lights: vec![Light::directional(Vec3::new(0.1, -1.0, 0.0), Vec3::ONE, 1.1)],
+            shadow_map: Shared::empty(),
+            environment: Shared::empty()
         }
     }
 }
@@ -67,70 +76,496 @@ fn fresnel_schlick(cos_theta: f32, f0: Vec3) -> Vec3
     //return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
     f0 + (1.0 - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powf(5.0)
 }
+// ----------------------------------------------------------------------------
+fn fresnel_schlick_roughness(cos_theta: f32, f0: Vec3, roughness: f32) -> Vec3
+{
+    f0 + (Vec3::splat(1.0 - roughness).max(f0) - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powf(5.0)
+}
+// ----------------------------------------------------------------------------
+fn fresnel_schlick_f90(cos_theta: f32, f0: Vec3, f90: f32) -> Vec3
+{
+    f0 + (Vec3::splat(f90) - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powf(5.0)
+}
+// ----------------------------------------------------------------------------
+fn distribution_charlie(n: &Vec3, h: Vec3, roughness: f32) -> f32
+{
+    let alpha = (roughness * roughness).max(1e-4);
+    let inv_alpha = 1.0 / alpha;
+    let n_dot_h = n.dot(h).max(0.0);
+    let sin2_h = (1.0 - n_dot_h * n_dot_h).max(0.0);
 
-impl Shader for PBRShader {
-    fn shade(&self, material: &Material, inputs: &ShaderIn) -> Vec4 {
-        let tex_coord = &inputs.tex_coord;
+    (2.0 + inv_alpha) * sin2_h.powf(inv_alpha * 0.5) / (2.0 * PI)
+}
+// ----------------------------------------------------------------------------
+fn visibility_neubelt(n_dot_v: f32, n_dot_l: f32) -> f32
+{
+    1.0 / (4.0 * (n_dot_l + n_dot_v - n_dot_l * n_dot_v)).max(1e-4)
+}
+// ----------------------------------------------------------------------------
+fn specular_ggx(n: &Vec3, v: Vec3, l: Vec3, h: Vec3, roughness: f32, f: Vec3) -> Vec3
+{
+    let ndf = distribution_ggx(n, h, roughness);
+    let g = geometry_smith(n, v, l, roughness);
+    let denominator = 4.0 * n.dot(v).max(0.0) * n.dot(l).max(0.0) + 0.0001;
+    ndf * g * f / denominator
+}
+
+fn refract(i: Vec3, n: Vec3, eta: f32) -> Vec3 {
+    let n_dot_i = n.dot(i);
+    let k = 1.0 - eta * eta * (1.0 - n_dot_i * n_dot_i);
+    if k < 0.0 {
+        i - 2.0 * n_dot_i * n
+    } else {
+        eta * i - (eta * n_dot_i + k.sqrt()) * n
+    }
+}
+
+// Directional albedo of the sheen lobe is approximated by a constant instead of a lookup table
+const SHEEN_ALBEDO: f32 = 0.157;
+
+// Material inputs after texturing, shared by the punctual and image based lighting
+struct Surface {
+    base_color: Vec3,
+    n: Vec3,
+    metallic: f32,
+    roughness: f32,
+    f0: Vec3,
+    f90: f32,
+
+    transmission: f32,
+    transmission_roughness: f32,
+    // Only set for volumes, thin walled surfaces don't refract or absorb
+    thickness: Option<f32>,
+    transmittance: Vec3,
+    ior: f32,
+
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+    clearcoat_n: Vec3,
+
+    sheen_color: Vec3,
+    sheen_roughness: f32
+}
+
+impl Surface {
+    fn sheen_scaling(&self) -> f32 {
+        1.0 - self.sheen_color.max_element() * SHEEN_ALBEDO
+    }
+
+    fn clearcoat_fresnel(&self, cos_theta: f32) -> f32 {
+        self.clearcoat * fresnel_schlick(cos_theta, Vec3::splat(0.04)).x
+    }
+}
+
+impl PBRShader {
+    pub fn shadow_light(&self) -> Option<usize> {
+        self.lights.iter().position(|light| light.kind == LightKind::Directional)
+    }
+
+    fn sample(&self, texture: &Texture, inputs: &ShaderIn) -> Option<Vec4> {
+        texture.image.try_as_ref().map(|image| {
+            let tex_coord = texture.tex_coord(inputs.tex_coord, inputs.tex_coord_1);
+            image.sample_pixel(tex_coord.x, tex_coord.y, self.sample_bilinear)
+        })
+    }
+
+    fn apply_normal_map(&self, n: Vec3, texture: &Texture, scale: f32, inputs: &ShaderIn) -> Vec3 {
+        if let Some(sample) = self.sample(texture, inputs) {
+            let tangent = inputs.tangent;
+            let t = tangent.xyz() - n * n.dot(tangent.xyz());
+            if t.length_squared() > 1e-8 {
+                let t = t.normalize();
+                let b = n.cross(t) * if tangent.w < 0.0 { -1.0 } else { 1.0 };
+
+                let sample = sample.xyz() * 2.0 - 1.0;
+                let sample = Vec3::new(sample.x * scale, sample.y * scale, sample.z);
+                return (t * sample.x + b * sample.y + n * sample.z).normalize();
+            }
+        }
+        n
+    }
 
-        let mut base_color = material.base_color_factor.xyz();
-        if let Some(base_color_texture) = material.base_color_texture.try_as_ref() {
-            base_color *= base_color_texture.sample_pixel(tex_coord.x, tex_coord.y, self.sample_bilinear).xyz();
+    fn base_color(&self, material: &Material, inputs: &ShaderIn) -> Vec3 {
+        let mut base_color = material.base_color_factor.xyz() * inputs.color.xyz();
+        if let Some(sample) = self.sample(&material.base_color_texture, inputs) {
+            base_color *= sample.xyz();
         }
+        base_color
+    }
+
+    fn surface(&self, material: &Material, inputs: &ShaderIn) -> Surface {
 
-        let n = &inputs.normal;
+        let base_color = self.base_color(material, inputs);
+
+        let geometric_n = inputs.normal.normalize();
+        let n = self.apply_normal_map(geometric_n, &material.normal_texture, material.normal_scale, inputs);
 
         let mut metallic = material.metallic_factor;
         let mut roughness = material.roughness_factor;
-        if let Some(metallic_roughness_texture) = material.metallic_roughness_texture.try_as_ref() {
-            let metallic_roughness = metallic_roughness_texture.sample_pixel(tex_coord.x, tex_coord.y, self.sample_bilinear).yz();
-            metallic *= metallic_roughness.y;
-            roughness *= metallic_roughness.x;
+        if let Some(sample) = self.sample(&material.metallic_roughness_texture, inputs) {
+            metallic *= sample.z;
+            roughness *= sample.y;
+        }
+
+        let specular = &material.specular;
+        let mut specular_factor = specular.factor;
+        if let Some(sample) = self.sample(&specular.texture, inputs) {
+            specular_factor *= sample.w;
+        }
+        let mut specular_color = specular.color_factor;
+        if let Some(sample) = self.sample(&specular.color_texture, inputs) {
+            specular_color *= sample.xyz();
         }
 
+        let ior = material.ior;
+        let dielectric_f0 = (Vec3::splat(((ior - 1.0) / (ior + 1.0)).powi(2)) * specular_color).min(Vec3::ONE) * specular_factor;
+        let f0 = lerp_v3(dielectric_f0, base_color, metallic);
+        let f90 = lerp(specular_factor, 1.0, metallic);
+
+        let mut transmission = 0.0;
+        if let Some(extension) = &material.transmission {
+            transmission = extension.factor;
+            if let Some(sample) = self.sample(&extension.texture, inputs) {
+                transmission *= sample.x;
+            }
+        }
+
+        let mut thickness = None;
+        let mut transmittance = Vec3::ONE;
+        if let Some(volume) = &material.volume {
+            let mut volume_thickness = volume.thickness_factor;
+            if let Some(sample) = self.sample(&volume.thickness_texture, inputs) {
+                volume_thickness *= sample.y;
+            }
+            if volume.attenuation_distance.is_finite() {
+                let exponent = volume_thickness / volume.attenuation_distance;
+                transmittance = Vec3::new(
+                    volume.attenuation_color.x.powf(exponent),
+                    volume.attenuation_color.y.powf(exponent),
+                    volume.attenuation_color.z.powf(exponent)
+                );
+            }
+            thickness = Some(volume_thickness);
+        }
+
+        let mut clearcoat = 0.0;
+        let mut clearcoat_roughness = 0.0;
+        let mut clearcoat_n = geometric_n;
+        if let Some(extension) = &material.clearcoat {
+            clearcoat = extension.factor;
+            if let Some(sample) = self.sample(&extension.texture, inputs) {
+                clearcoat *= sample.x;
+            }
+            clearcoat_roughness = extension.roughness_factor;
+            if let Some(sample) = self.sample(&extension.roughness_texture, inputs) {
+                clearcoat_roughness *= sample.y;
+            }
+            clearcoat_n = self.apply_normal_map(geometric_n, &extension.normal_texture, extension.normal_scale, inputs);
+        }
+
+        let mut sheen_color = Vec3::ZERO;
+        let mut sheen_roughness = 0.0;
+        if let Some(extension) = &material.sheen {
+            sheen_color = extension.color_factor;
+            if let Some(sample) = self.sample(&extension.color_texture, inputs) {
+                sheen_color *= sample.xyz();
+            }
+            sheen_roughness = extension.roughness_factor;
+            if let Some(sample) = self.sample(&extension.roughness_texture, inputs) {
+                sheen_roughness *= sample.w;
+            }
+        }
+
+        Surface {
+            base_color: base_color,
+            n: n,
+            metallic: metallic,
+            roughness: roughness,
+            f0: f0,
+            f90: f90,
+            transmission: transmission,
+            // Rougher transmission for higher indices of refraction, as in the reference implementation
+            transmission_roughness: roughness * (ior * 2.0 - 2.0).clamp(0.0, 1.0),
+            thickness: thickness,
+            transmittance: transmittance,
+            ior: ior,
+            clearcoat: clearcoat,
+            clearcoat_roughness: clearcoat_roughness,
+            clearcoat_n: clearcoat_n,
+            sheen_color: sheen_color,
+            sheen_roughness: sheen_roughness
+        }
+    }
+
+    // Layered BRDF for a single light: clearcoat over sheen over the metallic/dielectric base,
+    // where the dielectric diffuse lobe is partially replaced by transmission
+    fn evaluate_light(&self, surface: &Surface, v: Vec3, l: Vec3) -> Vec3 {
+        let n = &surface.n;
+        let n_dot_v = n.dot(v).max(0.0);
+        let n_dot_l = n.dot(l).max(0.0);
+
+        let mut color = Vec3::ZERO;
+        if n_dot_l > 0.0 {
+            let h = (v + l).normalize();
+            let f = fresnel_schlick_f90(h.dot(v).clamp(0.0, 1.0), surface.f0, surface.f90);
+            let specular = specular_ggx(n, v, l, h, surface.roughness, f);
+            let diffuse = (Vec3::ONE - f) * (1.0 - surface.metallic) * (1.0 - surface.transmission) * surface.base_color / PI;
+
+            color = (diffuse + specular) * n_dot_l;
+        }
+
+        // Light passing through the surface from behind, mirrored to the viewing side
+        let n_dot_l_back = n.dot(-l).max(0.0);
+        if surface.transmission > 0.0 && n_dot_l_back > 0.0 {
+            let l_mirror = l + 2.0 * *n * n_dot_l_back;
+            let h = (v + l_mirror).normalize();
+            let f = fresnel_schlick_f90(h.dot(v).clamp(0.0, 1.0), surface.f0, surface.f90);
+            let btdf = specular_ggx(n, v, l_mirror, h, surface.transmission_roughness, Vec3::ONE - f);
+
+            color += btdf * surface.base_color * surface.transmittance * surface.transmission * (1.0 - surface.metallic) * n_dot_l_back;
+        }
+
+        if surface.sheen_color != Vec3::ZERO {
+            let sheen = if n_dot_l > 0.0 {
+                let h = (v + l).normalize();
+                surface.sheen_color * distribution_charlie(n, h, surface.sheen_roughness) * visibility_neubelt(n_dot_v, n_dot_l) * n_dot_l
+            } else {
+                Vec3::ZERO
+            };
+            color = color * surface.sheen_scaling() + sheen;
+        }
+
+        if surface.clearcoat > 0.0 {
+            let nc = &surface.clearcoat_n;
+            let nc_dot_l = nc.dot(l).max(0.0);
+            let coat = if nc_dot_l > 0.0 {
+                let h = (v + l).normalize();
+                let f = fresnel_schlick(h.dot(v).clamp(0.0, 1.0), Vec3::splat(0.04));
+                specular_ggx(nc, v, l, h, surface.clearcoat_roughness, f) * nc_dot_l
+            } else {
+                Vec3::ZERO
+            };
+            color = color * (1.0 - surface.clearcoat_fresnel(nc.dot(v).max(0.0))) + coat * surface.clearcoat;
+        }
+
+        color
+    }
+
+    fn evaluate_environment(&self, surface: &Surface, environment: &Environment, v: Vec3) -> Vec3 {
+        let n = surface.n;
+        let n_dot_v = n.dot(v).max(0.0);
+        let f = fresnel_schlick_roughness(n_dot_v, surface.f0, surface.roughness);
+        let kd = (Vec3::ONE - f) * (1.0 - surface.metallic);
+
+        let r = 2.0 * n.dot(v) * n - v;
+        let brdf = environment.brdf(n_dot_v, surface.roughness);
+        let specular = environment.specular(r, surface.roughness) * (surface.f0 * brdf.x + surface.f90 * brdf.y);
+
+        let diffuse = environment.diffuse(n) * surface.base_color;
+        let transmitted = if surface.transmission > 0.0 {
+            let direction = match surface.thickness {
+                Some(thickness) if thickness > 0.0 => refract(-v, n, 1.0 / surface.ior),
+                _ => -v
+            };
+            environment.specular(direction, surface.transmission_roughness) * surface.base_color * surface.transmittance
+        } else {
+            Vec3::ZERO
+        };
+
+        let mut color = kd * lerp_v3(diffuse, transmitted, surface.transmission) + specular;
+
+        if surface.sheen_color != Vec3::ZERO {
+            color = color * surface.sheen_scaling() + environment.diffuse(n) * surface.sheen_color * SHEEN_ALBEDO;
+        }
+
+        if surface.clearcoat > 0.0 {
+            let nc = surface.clearcoat_n;
+            let nc_dot_v = nc.dot(v).max(0.0);
+            let rc = 2.0 * nc.dot(v) * nc - v;
+            let brdf = environment.brdf(nc_dot_v, surface.clearcoat_roughness);
+            let coat = environment.specular(rc, surface.clearcoat_roughness) * (0.04 * brdf.x + brdf.y);
+            color = color * (1.0 - surface.clearcoat_fresnel(nc_dot_v)) + coat * surface.clearcoat;
+        }
+
+        color
+    }
+}
+
+impl Shader for PBRShader {
+    fn shade(&self, material: &Material, inputs: &ShaderIn) -> Vec4 {
+        // Lines and points have no surface to light
+        if material.unlit || inputs.primitive_type != PrimitiveType::Triangles {
+            return Vec4::from((self.base_color(material, inputs), 1.0));
+        }
+
+        let surface = self.surface(material, inputs);
+
         let mut occlusion = 1.0;
-        if let Some(occlusion_texture) = material.occlusion_texture.try_as_ref() {
-            occlusion = lerp(occlusion_texture.sample_pixel(tex_coord.x, tex_coord.y, self.sample_bilinear).x, 1.0, 1.0 - material.occlusion_strength);
+        if let Some(sample) = self.sample(&material.occlusion_texture, inputs) {
+            occlusion = lerp(sample.x, 1.0, 1.0 - material.occlusion_strength);
         }
 
-        let mut emission = Vec3::default();
-        if let Some(emissive_texture) = material.emissive_texture.try_as_ref() {
-            emission = emissive_texture.sample_pixel(tex_coord.x, tex_coord.y, self.sample_bilinear).xyz() * material.emissive_factor;
+        let mut emission = material.emissive_factor * material.emissive_strength;
+        if let Some(sample) = self.sample(&material.emissive_texture, inputs) {
+            emission *= sample.xyz();
         }
 
         let v = (self.view_position - inputs.position).normalize();
         let ao = 0.1;
 
-        let mut f0 = Vec3::splat(0.04);
-        f0 = lerp_v3(f0, base_color, metallic);
+        let shadow_light = self.shadow_light();
 
-        let lo;
-        {
-            let l = (-Vec3::new(0.1, -1.0, 0.0)).normalize();
-            let h = (v + l).normalize();
-            let radiance = Vec3::splat(1.1);
+        let mut lo = Vec3::ZERO;
+        for (i, light) in self.lights.iter().enumerate() {
+            let (l, radiance) = light.incident(inputs.position);
+
+            let shadow = match (self.shadow_map.try_as_ref(), shadow_light) {
+                (Some(shadow_map), Some(shadow_light)) if shadow_light == i => shadow_map.shadow_factor(inputs.position, inputs.normal.normalize()),
+                _ => 1.0
+            };
+
+            lo += self.evaluate_light(&surface, v, l) * radiance * shadow;
+        }
 
-            let ndf = distribution_ggx(n, h, roughness);   
-            let g = geometry_smith(n, v, l, roughness);      
-            let f = fresnel_schlick(h.dot(v).clamp(0.0, 1.0), f0);
+        let ambient = match self.environment.try_as_ref() {
+            Some(environment) => self.evaluate_environment(&surface, &environment, v),
+            None => Vec3::splat(0.03) * surface.base_color * ao
+        };
 
-            let numerator = ndf * g * f;
-            let denominator = 4.0 * n.dot(v).max(0.0) * n.dot(l).max(0.0) + 0.0001;
-            let specular = numerator / denominator;
+        // The clearcoat layer sits on top of the emissive base as well
+        let emission = emission * (1.0 - surface.clearcoat_fresnel(surface.clearcoat_n.dot(v).max(0.0)));
 
-            let ks = f;
-            let mut kd = Vec3::splat(1.0) - ks;
-            kd *= 1.0 - metallic;
+        let color = (ambient + lo) * occlusion + emission;
 
-            let n_dot_l = n.dot(l).max(0.0);
-            lo = (kd * base_color / PI + specular) * radiance * n_dot_l;
+        Vec4::from((color, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::{Image, ImageData, TexelFormat};
+
+    fn shader_in(tangent: Vec4) -> ShaderIn {
+        ShaderIn {
+            position: Vec3::ZERO,
+            normal: Vec3::Z,
+            tangent: tangent,
+            tex_coord: Vec2::splat(0.5),
+            tex_coord_1: Vec2::ZERO,
+            color: Vec4::ONE,
+            primitive_type: PrimitiveType::Triangles
         }
+    }
 
-        let ambient = Vec3::splat(0.03) * base_color * ao;
+    // Material with a single texel normal map, the normal is given in tangent space
+    fn normal_mapped(normal: Vec3) -> Material {
+        let texel = (normal.normalize() * 0.5 + 0.5) * 255.0;
+        let data = vec![texel.x.round() as u8, texel.y.round() as u8, texel.z.round() as u8];
+        Material {
+            roughness_factor: 1.0,
+            normal_texture: Texture::new(Shared::new(Image::new(ImageData::U8(data), IVec2::ONE, TexelFormat::RGB8))),
+            ..Material::default()
+        }
+    }
 
-        let mut color = (ambient + lo) * occlusion + emission;
-        color = color / (color + 1.0);
-        color = color.powf(1.0 / 2.2);
+    fn lit_from(direction: Vec3) -> PBRShader {
+        PBRShader {
+            view_position: Vec3::Z,
+            lights: vec![Light::directional(-direction, Vec3::ONE, 1.0)],
+            ..PBRShader::default()
+        }
+    }
 
-        Vec4::from((color, 1.0))
+    #[test]
+    fn normal_maps_tilt_along_the_tangent() {
+        let shader = lit_from(Vec3::X);
+        let tilted = shader.shade(&normal_mapped(Vec3::new(1.0, 0.0, 1.0)), &shader_in(Vec4::new(1.0, 0.0, 0.0, 1.0))).x;
+        let away = shader.shade(&normal_mapped(Vec3::new(-1.0, 0.0, 1.0)), &shader_in(Vec4::new(1.0, 0.0, 0.0, 1.0))).x;
+        assert!(tilted > away + 0.1, "{} {}", tilted, away);
+
+        // Without a usable tangent the geometric normal is kept
+        let flat = shader.shade(&Material::default(), &shader_in(Vec4::ZERO)).x;
+        let untangented = shader.shade(&normal_mapped(Vec3::new(1.0, 0.0, 1.0)), &shader_in(Vec4::ZERO)).x;
+        assert!((flat - untangented).abs() < 1e-3, "{} {}", flat, untangented);
+    }
+
+    #[test]
+    fn tangent_w_flips_the_bitangent() {
+        let shader = lit_from(Vec3::Y);
+        let material = normal_mapped(Vec3::new(0.0, 1.0, 1.0));
+        let right_handed = shader.shade(&material, &shader_in(Vec4::new(1.0, 0.0, 0.0, 1.0))).x;
+        let left_handed = shader.shade(&material, &shader_in(Vec4::new(1.0, 0.0, 0.0, -1.0))).x;
+        assert!(right_handed > left_handed + 0.1, "{} {}", right_handed, left_handed);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn vertex_colors_tint_the_base_color() {
+        let shader = lit_from(Vec3::Z);
+        let material = Material {
+            base_color_factor: Vec4::new(0.5, 1.0, 1.0, 1.0),
+            metallic_factor: 1.0,
+            ..Material::default()
+        };
+        let inputs = ShaderIn {
+            color: Vec4::new(1.0, 0.5, 0.0, 1.0),
+            ..shader_in(Vec4::ZERO)
+        };
+
+        // A metal reflects its base color, so the channels keep the ratio of the combined color
+        let color = shader.shade(&material, &inputs);
+        assert!((color.y / color.x - 1.0).abs() < 1e-4, "{:?}", color);
+        assert_eq!(color.z, 0.0);
+    }
+
+    #[test]
+    fn unlit_materials_ignore_lighting() {
+        let material = Material {
+            base_color_factor: Vec4::new(0.25, 0.5, 1.0, 1.0),
+            unlit: true,
+            ..Material::default()
+        };
+        let inputs = ShaderIn {
+            color: Vec4::new(1.0, 0.5, 1.0, 1.0),
+            ..shader_in(Vec4::ZERO)
+        };
+
+        for direction in [Vec3::Z, Vec3::NEG_Z] {
+            assert_eq!(lit_from(direction).shade(&material, &inputs), Vec4::new(0.25, 0.25, 1.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn lines_and_points_are_unlit() {
+        let material = Material {
+            base_color_factor: Vec4::new(0.25, 0.5, 1.0, 1.0),
+            ..Material::default()
+        };
+
+        // Their normal is arbitrary, the base color is used as is whichever way the light comes from
+        for primitive_type in [PrimitiveType::Lines, PrimitiveType::Points] {
+            let inputs = ShaderIn {
+                primitive_type: primitive_type,
+                ..shader_in(Vec4::ZERO)
+            };
+            for direction in [Vec3::Z, Vec3::NEG_Z] {
+                assert_eq!(lit_from(direction).shade(&material, &inputs), Vec4::new(0.25, 0.5, 1.0, 1.0));
+            }
+        }
+    }
+
+    #[test]
+    fn emissive_strength_scales_the_emission() {
+        let shader = lit_from(Vec3::NEG_Z);
+        let mut material = Material {
+            base_color_factor: Vec4::ZERO,
+            emissive_factor: Vec3::new(1.0, 0.5, 0.0),
+            ..Material::default()
+        };
+        let base = shader.shade(&material, &shader_in(Vec4::ZERO));
+
+        material.emissive_strength = 4.0;
+        let strong = shader.shade(&material, &shader_in(Vec4::ZERO));
+        assert!((strong.xyz() - base.xyz() - Vec3::new(3.0, 1.5, 0.0)).abs().max_element() < 1e-5, "{:?} {:?}", base, strong);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::glam::*;
+use crate::resources::{Model, Node};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    // Keyframes store an in-tangent, value and out-tangent
+    CubicSpline
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelTarget {
+    Translation,
+    Rotation,
+    Scale,
+    Weights
+}
+
+#[derive(Clone)]
+pub struct Channel {
+    pub node: usize,
+    pub target: ChannelTarget,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    // Flattened keyframe values, every element has `components` floats
+    pub values: Vec<f32>,
+    pub components: usize
+}
+
+impl Channel {
+    fn element(&self, index: usize) -> &[f32] {
+        &self.values[index * self.components..(index + 1) * self.components]
+    }
+
+    // Value of keyframe i, skipping the tangents of cubic splines
+    fn value(&self, i: usize) -> &[f32] {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.element(i * 3 + 1),
+            _ => self.element(i)
+        }
+    }
+
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        let last = self.times.len() - 1;
+        if time <= self.times[0] {
+            return self.value(0).to_vec();
+        }
+        if time >= self.times[last] {
+            return self.value(last).to_vec();
+        }
+
+        let i = self.times.partition_point(|t| *t <= time) - 1;
+        let delta = self.times[i + 1] - self.times[i];
+        let s = (time - self.times[i]) / delta;
+
+        let mut result: Vec<f32> = match self.interpolation {
+            Interpolation::Step => self.value(i).to_vec(),
+            Interpolation::Linear if self.target == ChannelTarget::Rotation => {
+                let a = Quat::from_slice(self.value(i));
+                let b = Quat::from_slice(self.value(i + 1));
+                return a.slerp(b, s).to_array().to_vec();
+            },
+            Interpolation::Linear => {
+                self.value(i).iter().zip(self.value(i + 1)).map(|(a, b)| a + (b - a) * s).collect()
+            },
+            Interpolation::CubicSpline => {
+                let s2 = s * s;
+                let s3 = s2 * s;
+                let v0 = self.element(i * 3 + 1);
+                let b0 = self.element(i * 3 + 2);
+                let a1 = self.element((i + 1) * 3);
+                let v1 = self.element((i + 1) * 3 + 1);
+
+                (0..self.components).map(|c| {
+                    (2.0 * s3 - 3.0 * s2 + 1.0) * v0[c]
+                        + (s3 - 2.0 * s2 + s) * delta * b0[c]
+                        + (-2.0 * s3 + 3.0 * s2) * v1[c]
+                        + (s3 - s2) * delta * a1[c]
+                }).collect()
+            }
+        };
+
+        if self.target == ChannelTarget::Rotation {
+            result = Quat::from_slice(&result).normalize().to_array().to_vec();
+        }
+        result
+    }
+}
+
+#[derive(Clone)]
+pub struct Animation {
+    pub name: String,
+    pub channels: Vec<Channel>,
+    pub duration: f32
+}
+
+impl Animation {
+    // Writes the sampled values into the local transforms of the targeted nodes
+    pub fn apply(&self, nodes: &mut [Node], time: f32) {
+        for channel in &self.channels {
+            if channel.times.is_empty() {
+                continue;
+            }
+
+            let value = channel.sample(time);
+            let node = &mut nodes[channel.node];
+            match channel.target {
+                ChannelTarget::Translation => node.translation = Vec3::from_slice(&value),
+                ChannelTarget::Rotation => node.rotation = Quat::from_slice(&value),
+                ChannelTarget::Scale => node.scale = Vec3::from_slice(&value),
+                ChannelTarget::Weights => node.weights = value
+            }
+        }
+    }
+}
+
+// Local transform of a node, as far as animation channels can change it
+struct NodePose {
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+    weights: Vec<f32>
+}
+
+pub struct AnimationPlayer {
+    pub animation: usize,
+    pub time: f32,
+    pub speed: f32,
+    pub looping: bool,
+    pub playing: bool,
+    // Captured before the first clip is applied, channels of the previous clip are undone with it
+    rest_pose: Vec<NodePose>,
+    applied: Option<usize>
+}
+
+impl Default for AnimationPlayer {
+    fn default() -> Self {
+        AnimationPlayer {
+            animation: 0,
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+            playing: true,
+            rest_pose: Vec::new(),
+            applied: None
+        }
+    }
+}
+
+impl AnimationPlayer {
+    pub fn play(&mut self, animation: usize) {
+        self.animation = animation;
+        self.time = 0.0;
+        self.playing = true;
+    }
+
+    // Advances the clock, applies the active clip and refreshes the world transforms of the model
+    pub fn update(&mut self, model: &mut Model, delta_time: f32) {
+        let animation = match model.animations.get(self.animation) {
+            Some(animation) => animation,
+            None => return
+        };
+
+        if self.playing {
+            self.time += delta_time * self.speed;
+            if self.looping && animation.duration > 0.0 {
+                self.time = self.time.rem_euclid(animation.duration);
+            } else {
+                self.time = self.time.clamp(0.0, animation.duration);
+            }
+        }
+
+        if self.applied != Some(self.animation) {
+            if self.applied.is_none() {
+                self.rest_pose = model.nodes.iter().map(|node| NodePose {
+                    translation: node.translation,
+                    rotation: node.rotation,
+                    scale: node.scale,
+                    weights: node.weights.clone()
+                }).collect();
+            } else {
+                for (node, pose) in model.nodes.iter_mut().zip(&self.rest_pose) {
+                    node.translation = pose.translation;
+                    node.rotation = pose.rotation;
+                    node.scale = pose.scale;
+                    node.weights = pose.weights.clone();
+                }
+            }
+            self.applied = Some(self.animation);
+        }
+
+        animation.apply(&mut model.nodes, self.time);
+        model.update_world_transforms();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(interpolation: Interpolation, values: Vec<f32>) -> Channel {
+        Channel {
+            node: 0,
+            target: ChannelTarget::Translation,
+            interpolation: interpolation,
+            times: vec![0.0, 2.0],
+            values: values,
+            components: 1
+        }
+    }
+
+    #[test]
+    fn step_and_linear_sampling() {
+        let step = channel(Interpolation::Step, vec![1.0, 3.0]);
+        assert_eq!(step.sample(1.9), vec![1.0]);
+        assert_eq!(step.sample(2.0), vec![3.0]);
+
+        let linear = channel(Interpolation::Linear, vec![1.0, 3.0]);
+        assert_eq!(linear.sample(-1.0), vec![1.0]);
+        assert_eq!(linear.sample(0.5), vec![1.5]);
+        assert_eq!(linear.sample(5.0), vec![3.0]);
+    }
+
+    #[test]
+    fn cubic_spline_sampling() {
+        // In-tangent, value and out-tangent per keyframe, a tangent of 1 per second matches the straight line
+        let cubic = channel(Interpolation::CubicSpline, vec![0.0, 1.0, 1.0, 1.0, 3.0, 0.0]);
+        assert!((cubic.sample(1.0)[0] - 2.0).abs() < 1e-5);
+        assert_eq!(cubic.sample(0.0), vec![1.0]);
+        assert_eq!(cubic.sample(2.0), vec![3.0]);
+
+        let flat = channel(Interpolation::CubicSpline, vec![0.0, 1.0, 0.0, 0.0, 3.0, 0.0]);
+        assert!((flat.sample(0.5)[0] - (1.0 + 2.0 * 0.15625)).abs() < 1e-5);
+    }
+
+    fn clip(target: ChannelTarget, values: Vec<f32>) -> Animation {
+        Animation {
+            name: String::from("Clip"),
+            channels: vec![Channel {
+                node: 0,
+                target: target,
+                interpolation: Interpolation::Step,
+                times: vec![0.0],
+                values: values,
+                components: 3
+            }],
+            duration: 0.0
+        }
+    }
+
+    #[test]
+    fn switching_clips_restores_the_rest_pose() {
+        let mut model = Model {
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            nodes: vec![Node {
+                name: String::from("Node"),
+                parent: None,
+                children: Vec::new(),
+                translation: Vec3::Y,
+                rotation: Quat::IDENTITY,
+                scale: Vec3::ONE,
+                weights: Vec::new(),
+                world_matrix: Mat4::IDENTITY,
+                meshes: Vec::new(),
+                light: None,
+                camera: None,
+                skin: None
+            }],
+            scenes: Vec::new(),
+            scene: 0,
+            lights: Vec::new(),
+            cameras: Vec::new(),
+            skins: Vec::new(),
+            animations: vec![clip(ChannelTarget::Translation, vec![5.0, 0.0, 0.0]), clip(ChannelTarget::Scale, vec![2.0, 2.0, 2.0])]
+        };
+
+        let mut player = AnimationPlayer::default();
+        player.update(&mut model, 0.1);
+        assert_eq!(model.nodes[0].translation, Vec3::new(5.0, 0.0, 0.0));
+
+        // The second clip doesn't animate the translation, it must not keep the pose of the first one
+        player.play(1);
+        player.update(&mut model, 0.1);
+        assert_eq!(model.nodes[0].translation, Vec3::Y);
+        assert_eq!(model.nodes[0].scale, Vec3::splat(2.0));
+        assert_eq!(model.nodes[0].world_matrix, Mat4::from_scale_rotation_translation(Vec3::splat(2.0), Quat::IDENTITY, Vec3::Y));
+    }
+}
@@ -0,0 +1,55 @@
+use crate::glam::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    // Vertical field of view in radians, an infinite projection is used without a far plane
+    Perspective { y_fov: f32, aspect_ratio: Option<f32>, z_near: f32, z_far: Option<f32> },
+    // Half extents of the view volume
+    Orthographic { x_mag: f32, y_mag: f32, z_near: f32, z_far: f32 }
+}
+
+// Camera looking down its local -Z axis, placed by the world transform of its node
+#[derive(Clone, Debug)]
+pub struct Camera {
+    pub name: String,
+    pub projection: Projection
+}
+
+impl Camera {
+    pub fn perspective(y_fov: f32, z_near: f32, z_far: Option<f32>) -> Self {
+        Camera {
+            name: String::from("Perspective"),
+            projection: Projection::Perspective { y_fov: y_fov, aspect_ratio: None, z_near: z_near, z_far: z_far }
+        }
+    }
+
+    pub fn orthographic(x_mag: f32, y_mag: f32, z_near: f32, z_far: f32) -> Self {
+        Camera {
+            name: String::from("Orthographic"),
+            projection: Projection::Orthographic { x_mag: x_mag, y_mag: y_mag, z_near: z_near, z_far: z_far }
+        }
+    }
+
+    // The aspect ratio of the viewport is used for perspective cameras, orthographic cameras keep their extents
+    pub fn proj_matrix(&self, aspect_ratio: f32) -> Mat4 {
+        match self.projection {
+            Projection::Perspective { y_fov, z_near, z_far: Some(z_far), .. } => Mat4::perspective_rh(y_fov, aspect_ratio, z_near, z_far),
+            Projection::Perspective { y_fov, z_near, z_far: None, .. } => Mat4::perspective_infinite_rh(y_fov, aspect_ratio, z_near),
+            Projection::Orthographic { x_mag, y_mag, z_near, z_far } => Mat4::orthographic_rh(-x_mag, x_mag, -y_mag, y_mag, z_near, z_far)
+        }
+    }
+
+    pub fn z_near(&self) -> f32 {
+        match self.projection {
+            Projection::Perspective { z_near, .. } => z_near,
+            Projection::Orthographic { z_near, .. } => z_near
+        }
+    }
+
+    pub fn z_far(&self) -> f32 {
+        match self.projection {
+            Projection::Perspective { z_far, .. } => z_far.unwrap_or(f32::INFINITY),
+            Projection::Orthographic { z_far, .. } => z_far
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ResourceError {
+    Io { path: String, source: std::io::Error },
+    // The file was read but its contents could not be parsed
+    Decode { path: String, message: String },
+    Unsupported { path: String, feature: String },
+    // Well formed data that breaks the rules of the format, like out of range indices
+    Validation { path: String, message: String }
+}
+
+pub type ResourceResult<T> = Result<T, ResourceError>;
+
+// Parsers don't know where their input came from, the loader fills in the path with `with_path`
+impl ResourceError {
+    pub fn io(path: &str, source: std::io::Error) -> Self {
+        ResourceError::Io { path: String::from(path), source: source }
+    }
+
+    pub fn decode(message: impl Into<String>) -> Self {
+        ResourceError::Decode { path: String::new(), message: message.into() }
+    }
+
+    pub fn unsupported(feature: impl Into<String>) -> Self {
+        ResourceError::Unsupported { path: String::new(), feature: feature.into() }
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        ResourceError::Validation { path: String::new(), message: message.into() }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            ResourceError::Io { path, .. } => path,
+            ResourceError::Decode { path, .. } => path,
+            ResourceError::Unsupported { path, .. } => path,
+            ResourceError::Validation { path, .. } => path
+        }
+    }
+
+    pub fn with_path(mut self, asset_path: &str) -> Self {
+        match &mut self {
+            ResourceError::Io { path, .. } => *path = String::from(asset_path),
+            ResourceError::Decode { path, .. } => *path = String::from(asset_path),
+            ResourceError::Unsupported { path, .. } => *path = String::from(asset_path),
+            ResourceError::Validation { path, .. } => *path = String::from(asset_path)
+        }
+        self
+    }
+
+    pub fn from_gltf(path: &str, error: gltf::Error) -> Self {
+        let error = match error {
+            gltf::Error::Io(source) => return ResourceError::io(path, source),
+            gltf::Error::Validation(errors) => ResourceError::validation(errors.iter()
+                .map(|(json_path, error)| format!("{}: {}", json_path, error))
+                .collect::<Vec<_>>()
+                .join(", ")),
+            gltf::Error::UnsupportedImageEncoding | gltf::Error::UnsupportedImageFormat(_) | gltf::Error::UnsupportedScheme => ResourceError::unsupported(error.to_string()),
+            gltf::Error::BufferLength { .. } | gltf::Error::MissingBlob | gltf::Error::ExternalReferenceInSliceImport => ResourceError::validation(error.to_string()),
+            _ => ResourceError::decode(error.to_string())
+        };
+        error.with_path(path)
+    }
+}
+
+impl fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceError::Io { path, source } => write!(f, "Failed to read '{}'. ({})", path, source),
+            ResourceError::Decode { path, message } => write!(f, "Failed to decode '{}'. ({})", path, message),
+            ResourceError::Unsupported { path, feature } => write!(f, "Unsupported feature in '{}'. ({})", path, feature),
+            ResourceError::Validation { path, message } => write!(f, "Invalid data in '{}'. ({})", path, message)
+        }
+    }
+}
+
+impl std::error::Error for ResourceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ResourceError::Io { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+
+use crate::glam::*;
+use crate::resources::Vertex;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalGeneration {
+    Flat,
+    // Angle weighted average over all faces sharing a position
+    Smooth
+}
+
+fn position_key(position: Vec3) -> [u32; 3] {
+    [position.x.to_bits(), position.y.to_bits(), position.z.to_bits()]
+}
+
+fn vertex_key(vertex: &Vertex) -> [u32; 8] {
+    [
+        vertex.position.x.to_bits(), vertex.position.y.to_bits(), vertex.position.z.to_bits(),
+        vertex.normal.x.to_bits(), vertex.normal.y.to_bits(), vertex.normal.z.to_bits(),
+        vertex.tex_coord.x.to_bits(), vertex.tex_coord.y.to_bits()
+    ]
+}
+
+// Angle at p0 between the edges towards p1 and p2, after projecting them onto the plane of n
+fn corner_angle(p0: Vec3, p1: Vec3, p2: Vec3, n: Vec3) -> f32 {
+    let e1 = p1 - p0;
+    let e2 = p2 - p0;
+    let e1 = (e1 - n * n.dot(e1)).normalize_or_zero();
+    let e2 = (e2 - n * n.dot(e2)).normalize_or_zero();
+    e1.dot(e2).clamp(-1.0, 1.0).acos()
+}
+
+fn any_tangent(n: Vec3) -> Vec3 {
+    let up = if n.y.abs() < 0.999 { Vec3::Y } else { Vec3::X };
+    up.cross(n).normalize_or_zero()
+}
+
+// Returns the original index of every vertex, for attributes that have to follow the new layout
+pub fn generate_flat_normals(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) -> Vec<u32> {
+    let mut flat_vertices = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks_exact(3) {
+        let v0 = &vertices[triangle[0] as usize];
+        let v1 = &vertices[triangle[1] as usize];
+        let v2 = &vertices[triangle[2] as usize];
+        let normal = (v1.position - v0.position).cross(v2.position - v0.position).normalize_or_zero();
+
+        for i in triangle {
+            flat_vertices.push(Vertex {
+                normal: normal,
+                ..vertices[*i as usize].clone()
+            });
+        }
+    }
+
+    let sources = std::mem::replace(indices, (0..flat_vertices.len() as u32).collect());
+    *vertices = flat_vertices;
+    sources
+}
+
+pub fn generate_smooth_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut normals: HashMap<[u32; 3], Vec3> = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+        let p = [
+            vertices[triangle[0] as usize].position,
+            vertices[triangle[1] as usize].position,
+            vertices[triangle[2] as usize].position
+        ];
+        let normal = (p[1] - p[0]).cross(p[2] - p[0]).normalize_or_zero();
+
+        for c in 0..3 {
+            let angle = corner_angle(p[c], p[(c + 1) % 3], p[(c + 2) % 3], normal);
+            *normals.entry(position_key(p[c])).or_insert(Vec3::ZERO) += normal * angle;
+        }
+    }
+
+    for vertex in vertices.iter_mut() {
+        if let Some(normal) = normals.get(&position_key(vertex.position)) {
+            vertex.normal = normal.normalize_or_zero();
+        }
+    }
+}
+
+// Tangents following MikkTSpace: per corner tangents are projected onto the vertex normal,
+// weighted by the corner angle and averaged over corners that share position, normal, uv and
+// texture space orientation. Vertices used by corners of both orientations are split, the
+// original index of every vertex is returned.
+pub fn generate_tangents(vertices: &mut Vec<Vertex>, indices: &mut [u32]) -> Vec<u32> {
+    let mut welds: HashMap<[u32; 8], usize> = HashMap::new();
+    let weld_ids: Vec<usize> = vertices.iter().map(|vertex| {
+        let next = welds.len();
+        *welds.entry(vertex_key(vertex)).or_insert(next)
+    }).collect();
+
+    let triangle_count = indices.len() / 3;
+    let mut orientations = vec![true; triangle_count];
+    let mut tangents: HashMap<(usize, bool), Vec3> = HashMap::new();
+
+    for t in 0..triangle_count {
+        let i = [indices[t * 3] as usize, indices[t * 3 + 1] as usize, indices[t * 3 + 2] as usize];
+        let p = [vertices[i[0]].position, vertices[i[1]].position, vertices[i[2]].position];
+        // MikkTSpace expects v to point up, glTF texture coordinates have it pointing down
+        let uv = [vertices[i[0]].tex_coord, vertices[i[1]].tex_coord, vertices[i[2]].tex_coord].map(|uv| Vec2::new(uv.x, 1.0 - uv.y));
+
+        let d2 = p[1] - p[0];
+        let d3 = p[2] - p[0];
+        let t21 = uv[1] - uv[0];
+        let t31 = uv[2] - uv[0];
+
+        let signed_area = t21.x * t31.y - t21.y * t31.x;
+        let orientation = signed_area > 0.0;
+        orientations[t] = orientation;
+
+        let mut s = t31.y * d2 - t21.y * d3;
+        if !orientation {
+            s = -s;
+        }
+        if signed_area.abs() <= f32::EPSILON || s.length_squared() <= f32::EPSILON {
+            continue;
+        }
+
+        for c in 0..3 {
+            let n = vertices[i[c]].normal;
+            let projected = (s - n * n.dot(s)).normalize_or_zero();
+            let angle = corner_angle(p[c], p[(c + 1) % 3], p[(c + 2) % 3], n);
+            *tangents.entry((weld_ids[i[c]], orientation)).or_insert(Vec3::ZERO) += projected * angle;
+        }
+    }
+
+    // The first orientation a vertex is used with keeps the vertex, the other gets a copy
+    let mut sources: Vec<u32> = (0..vertices.len() as u32).collect();
+    let mut primary: HashMap<usize, bool> = HashMap::new();
+    let mut splits: HashMap<usize, u32> = HashMap::new();
+    for t in 0..triangle_count {
+        let orientation = orientations[t];
+        for c in 0..3 {
+            let index = indices[t * 3 + c] as usize;
+            let primary_orientation = *primary.entry(index).or_insert(orientation);
+            if primary_orientation != orientation {
+                let split = *splits.entry(index).or_insert_with(|| {
+                    vertices.push(vertices[index].clone());
+                    sources.push(index as u32);
+                    (vertices.len() - 1) as u32
+                });
+                indices[t * 3 + c] = split;
+            }
+        }
+    }
+
+    let assign = |vertex: &mut Vertex, tangent: Option<&Vec3>, orientation: bool| {
+        let tangent = tangent.copied().unwrap_or(Vec3::ZERO).normalize_or_zero();
+        let tangent = if tangent == Vec3::ZERO { any_tangent(vertex.normal) } else { tangent };
+        vertex.tangent = Vec4::from((tangent, if orientation { 1.0 } else { -1.0 }));
+    };
+
+    for (index, orientation) in primary {
+        let weld_id = weld_ids[index];
+        assign(&mut vertices[index], tangents.get(&(weld_id, orientation)), orientation);
+        if let Some(split) = splits.get(&index) {
+            assign(&mut vertices[*split as usize], tangents.get(&(weld_id, !orientation)), !orientation);
+        }
+    }
+    sources
+}
+
+// Newell's method, the length is twice the area of the polygon
+pub fn polygon_normal(points: &[Vec3]) -> Vec3 {
+    let mut normal = Vec3::ZERO;
+    for i in 0..points.len() {
+        let a = points[i];
+        let b = points[(i + 1) % points.len()];
+        normal += Vec3::new((a.y - b.y) * (a.z + b.z), (a.z - b.z) * (a.x + b.x), (a.x - b.x) * (a.y + b.y));
+    }
+    normal
+}
+
+fn is_ear(points: &[Vec2], remaining: &[usize], a: usize, b: usize, c: usize) -> bool {
+    let (pa, pb, pc) = (points[a], points[b], points[c]);
+    if (pb - pa).perp_dot(pc - pb) <= 0.0 {
+        return false;
+    }
+
+    !remaining.iter().filter(|i| **i != a && **i != b && **i != c).any(|i| {
+        let p = points[*i];
+        (pb - pa).perp_dot(p - pa) >= 0.0 && (pc - pb).perp_dot(p - pb) >= 0.0 && (pa - pc).perp_dot(p - pc) >= 0.0
+    })
+}
+
+// Ear clipping in the plane of the polygon, returns triangles as indices into the points
+pub fn triangulate_polygon(points: &[Vec3]) -> Vec<u32> {
+    let count = points.len();
+    if count < 3 {
+        return Vec::new();
+    }
+
+    let normal = polygon_normal(points).normalize_or_zero();
+    let mut remaining: Vec<usize> = (0..count).collect();
+    let mut indices = Vec::new();
+
+    if count > 3 && normal != Vec3::ZERO {
+        let tangent = any_tangent(normal);
+        let bitangent = normal.cross(tangent);
+        let projected: Vec<Vec2> = points.iter().map(|p| Vec2::new(p.dot(tangent), p.dot(bitangent))).collect();
+
+        let mut i = 0;
+        let mut attempts = 0;
+        while remaining.len() > 3 && attempts < remaining.len() {
+            let len = remaining.len();
+            i %= len;
+            let (a, b, c) = (remaining[(i + len - 1) % len], remaining[i], remaining[(i + 1) % len]);
+            if is_ear(&projected, &remaining, a, b, c) {
+                indices.extend_from_slice(&[a as u32, b as u32, c as u32]);
+                remaining.remove(i);
+                attempts = 0;
+            } else {
+                i += 1;
+                attempts += 1;
+            }
+        }
+    }
+
+    // Degenerate or self intersecting leftovers are fanned
+    for i in 2..remaining.len() {
+        indices.extend_from_slice(&[remaining[0] as u32, remaining[i - 1] as u32, remaining[i] as u32]);
+    }
+    indices
+}
+
+// Splits an index list into the runs between primitive restart values
+pub fn split_restart(indices: &[u32], restart: u32) -> Vec<Vec<u32>> {
+    indices.split(|index| *index == restart).filter(|run| !run.is_empty()).map(|run| run.to_vec()).collect()
+}
+
+// Every other triangle of a strip is flipped to keep the winding of the first one
+pub fn triangulate_strip(strip: &[u32]) -> Vec<u32> {
+    let mut indices = Vec::new();
+    for i in 2..strip.len() {
+        if i % 2 == 0 {
+            indices.extend_from_slice(&[strip[i - 2], strip[i - 1], strip[i]]);
+        } else {
+            indices.extend_from_slice(&[strip[i - 1], strip[i - 2], strip[i]]);
+        }
+    }
+    indices
+}
+
+pub fn triangulate_fan(fan: &[u32]) -> Vec<u32> {
+    let mut indices = Vec::new();
+    for i in 2..fan.len() {
+        indices.extend_from_slice(&[fan[i - 1], fan[i], fan[0]]);
+    }
+    indices
+}
+
+// Line strips and loops become separate segments, a loop closes back to its first vertex
+pub fn line_strip_to_lines(strip: &[u32], closed: bool) -> Vec<u32> {
+    let mut indices = Vec::new();
+    for i in 1..strip.len() {
+        indices.extend_from_slice(&[strip[i - 1], strip[i]]);
+    }
+    if closed && strip.len() > 2 {
+        indices.extend_from_slice(&[strip[strip.len() - 1], strip[0]]);
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_primitives(asset_path: &str) -> Vec<(Vec<Vertex>, Vec<u32>)> {
+        let (document, buffers, _) = gltf::import(asset_path).expect("Failed to load test model.");
+
+        let mut primitives = Vec::new();
+        for mesh in document.meshes() {
+            for primitive in mesh.primitives() {
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let mut vertices: Vec<Vertex> = reader.read_positions().unwrap().map(|position| Vertex {
+                    position: Vec3::from(position),
+                    ..Vertex::default()
+                }).collect();
+                if let Some(normals) = reader.read_normals() {
+                    for (i, normal) in normals.enumerate() {
+                        vertices[i].normal = Vec3::from(normal);
+                    }
+                }
+                if let Some(tex_coords) = reader.read_tex_coords(0) {
+                    for (i, tex_coord) in tex_coords.into_f32().enumerate() {
+                        vertices[i].tex_coord = Vec2::from(tex_coord);
+                    }
+                }
+                let indices = reader.read_indices().unwrap().into_u32().collect();
+                primitives.push((vertices, indices));
+            }
+        }
+        primitives
+    }
+
+    #[test]
+    fn tangents_are_orthonormal_with_unit_handedness() {
+        for (mut vertices, mut indices) in load_primitives("assets/test_models/Duck/glTF-Binary/Duck.glb") {
+            let index_count = indices.len();
+            generate_tangents(&mut vertices, &mut indices);
+
+            assert_eq!(indices.len(), index_count);
+            for index in indices {
+                let vertex = &vertices[index as usize];
+                let tangent = vertex.tangent.xyz();
+                assert!((tangent.length() - 1.0).abs() < 1e-3);
+                assert!(tangent.dot(vertex.normal.normalize()).abs() < 1e-3);
+                assert!(vertex.tangent.w == 1.0 || vertex.tangent.w == -1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn tangents_follow_the_u_direction() {
+        let mut vertices = vec![
+            Vertex { position: Vec3::new(0.0, 0.0, 0.0), normal: Vec3::Z, tex_coord: Vec2::new(0.0, 0.0), ..Vertex::default() },
+            Vertex { position: Vec3::new(1.0, 0.0, 0.0), normal: Vec3::Z, tex_coord: Vec2::new(1.0, 0.0), ..Vertex::default() },
+            Vertex { position: Vec3::new(0.0, 1.0, 0.0), normal: Vec3::Z, tex_coord: Vec2::new(0.0, 1.0), ..Vertex::default() },
+            Vertex { position: Vec3::new(-1.0, 0.0, 0.0), normal: Vec3::Z, tex_coord: Vec2::new(1.0, 0.0), ..Vertex::default() }
+        ];
+        let mut indices = vec![0, 1, 2, 0, 2, 3];
+        generate_tangents(&mut vertices, &mut indices);
+
+        // The second triangle is mirrored in texture space, so the shared vertices are split.
+        // With v pointing down the bitangent of the first triangle is -Y, opposite to N x T.
+        assert_eq!(vertices.len(), 6);
+        assert!(vertices[indices[1] as usize].tangent.abs_diff_eq(Vec4::new(1.0, 0.0, 0.0, -1.0), 1e-5));
+        assert!(vertices[indices[5] as usize].tangent.abs_diff_eq(Vec4::new(-1.0, 0.0, 0.0, 1.0), 1e-5));
+    }
+
+    #[test]
+    fn smooth_normals_match_authored_normals() {
+        for (mut vertices, indices) in load_primitives("assets/test_models/Duck/glTF-Binary/Duck.glb") {
+            let authored: Vec<Vec3> = vertices.iter().map(|vertex| vertex.normal).collect();
+            generate_smooth_normals(&mut vertices, &indices);
+
+            let agreeing = vertices.iter().zip(authored.iter()).filter(|(vertex, normal)| vertex.normal.dot(**normal) > 0.8).count();
+            assert!(agreeing as f32 > vertices.len() as f32 * 0.9);
+            for vertex in &vertices {
+                assert!((vertex.normal.length() - 1.0).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn flat_normals_are_face_normals() {
+        for (mut vertices, mut indices) in load_primitives("assets/test_models/BrainStem/glTF-Binary/BrainStem.glb") {
+            let triangle_count = indices.len() / 3;
+            generate_flat_normals(&mut vertices, &mut indices);
+
+            assert_eq!(vertices.len(), triangle_count * 3);
+            for triangle in vertices.chunks_exact(3) {
+                let normal = (triangle[1].position - triangle[0].position).cross(triangle[2].position - triangle[0].position);
+                assert!(triangle.iter().all(|vertex| vertex.normal == triangle[0].normal));
+                assert!(normal.length_squared() == 0.0 || normal.normalize().dot(triangle[0].normal) > 0.999);
+            }
+        }
+    }
+
+    #[test]
+    fn strips_and_fans_become_lists() {
+        let strip = triangulate_strip(&[0, 1, 2, 3, 4]);
+        assert_eq!(strip, vec![0, 1, 2, 2, 1, 3, 2, 3, 4]);
+
+        let fan = triangulate_fan(&[0, 1, 2, 3]);
+        assert_eq!(fan, vec![1, 2, 0, 2, 3, 0]);
+
+        let runs = split_restart(&[0, 1, 2, u32::MAX, 3, 4, 5, u32::MAX], u32::MAX);
+        assert_eq!(runs, vec![vec![0, 1, 2], vec![3, 4, 5]]);
+
+        assert_eq!(line_strip_to_lines(&[0, 1, 2], false), vec![0, 1, 1, 2]);
+        assert_eq!(line_strip_to_lines(&[0, 1, 2], true), vec![0, 1, 1, 2, 2, 0]);
+    }
+
+    #[test]
+    fn concave_polygons_are_ear_clipped() {
+        // L shape, a fan from the first corner would cover the notch
+        let points = [
+            Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(2.0, 1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0), Vec3::new(1.0, 2.0, 0.0), Vec3::new(0.0, 2.0, 0.0)
+        ];
+        let indices = triangulate_polygon(&points);
+        assert_eq!(indices.len(), 12);
+
+        let mut area = 0.0;
+        for triangle in indices.chunks_exact(3) {
+            let normal = (points[triangle[1] as usize] - points[triangle[0] as usize]).cross(points[triangle[2] as usize] - points[triangle[0] as usize]);
+            assert!(normal.z > 0.0);
+            area += normal.z * 0.5;
+        }
+        assert!((area - 3.0).abs() < 1e-5);
+    }
+}
@@ -1,79 +1,342 @@
 use crate::glam::IVec2;
 use crate::glam::Vec4;
 use crate::glam::Vec2;
+use crate::glam::Vec3;
+use crate::color::srgb_u8_to_linear;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TexelFormat {
+    R8,
+    RG8,
+    RGB8,
+    RGBA8,
+    RGB8Srgb,
+    RGBA8Srgb,
+    R16,
+    RG16,
+    RGB16,
+    RGBA16,
+    R32F,
+    RG32F,
+    RGB32F,
+    RGBA32F
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TexelComponent {
+    U8,
+    U16,
+    F32
+}
+
+impl TexelFormat {
+    pub fn channel_count(&self) -> usize {
+        match self {
+            TexelFormat::R8 | TexelFormat::R16 | TexelFormat::R32F => 1,
+            TexelFormat::RG8 | TexelFormat::RG16 | TexelFormat::RG32F => 2,
+            TexelFormat::RGB8 | TexelFormat::RGB8Srgb | TexelFormat::RGB16 | TexelFormat::RGB32F => 3,
+            TexelFormat::RGBA8 | TexelFormat::RGBA8Srgb | TexelFormat::RGBA16 | TexelFormat::RGBA32F => 4
+        }
+    }
+
+    pub fn component(&self) -> TexelComponent {
+        match self {
+            TexelFormat::R8 | TexelFormat::RG8 | TexelFormat::RGB8 | TexelFormat::RGBA8 | TexelFormat::RGB8Srgb | TexelFormat::RGBA8Srgb => TexelComponent::U8,
+            TexelFormat::R16 | TexelFormat::RG16 | TexelFormat::RGB16 | TexelFormat::RGBA16 => TexelComponent::U16,
+            TexelFormat::R32F | TexelFormat::RG32F | TexelFormat::RGB32F | TexelFormat::RGBA32F => TexelComponent::F32
+        }
+    }
+
+    pub fn is_srgb(&self) -> bool {
+        matches!(self, TexelFormat::RGB8Srgb | TexelFormat::RGBA8Srgb)
+    }
+
+    // Gray images are expanded to RGB on load, 16-bit and float data is always treated as linear
+    pub fn to_srgb(&self) -> Self {
+        match self {
+            TexelFormat::RGB8 => TexelFormat::RGB8Srgb,
+            TexelFormat::RGBA8 => TexelFormat::RGBA8Srgb,
+            _ => *self
+        }
+    }
+
+    pub fn from_channel_count(component: TexelComponent, channel_count: usize) -> Option<Self> {
+        match (component, channel_count) {
+            (TexelComponent::U8, 1) => Some(TexelFormat::R8),
+            (TexelComponent::U8, 2) => Some(TexelFormat::RG8),
+            (TexelComponent::U8, 3) => Some(TexelFormat::RGB8),
+            (TexelComponent::U8, 4) => Some(TexelFormat::RGBA8),
+            (TexelComponent::U16, 1) => Some(TexelFormat::R16),
+            (TexelComponent::U16, 2) => Some(TexelFormat::RG16),
+            (TexelComponent::U16, 3) => Some(TexelFormat::RGB16),
+            (TexelComponent::U16, 4) => Some(TexelFormat::RGBA16),
+            (TexelComponent::F32, 1) => Some(TexelFormat::R32F),
+            (TexelComponent::F32, 2) => Some(TexelFormat::RG32F),
+            (TexelComponent::F32, 3) => Some(TexelFormat::RGB32F),
+            (TexelComponent::F32, 4) => Some(TexelFormat::RGBA32F),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum ImageData {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    F32(Vec<f32>)
+}
+
+impl ImageData {
+    pub fn component(&self) -> TexelComponent {
+        match self {
+            ImageData::U8(_) => TexelComponent::U8,
+            ImageData::U16(_) => TexelComponent::U16,
+            ImageData::F32(_) => TexelComponent::F32
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            ImageData::U8(data) => data.len(),
+            ImageData::U16(data) => data.len(),
+            ImageData::F32(data) => data.len()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
 
 #[derive(Clone)]
 pub struct Image {
-    pub data: Vec<u8>,
+    pub data: ImageData,
     pub dimensions: IVec2,
-    pub channel_count: i32,
+    pub format: TexelFormat,
 
     inv_dimensions: Vec2
 }
 
 impl Image {
-    pub fn new(data: Vec<u8>, dimensions: IVec2, channel_count: i32) -> Self {
+    pub fn new(data: ImageData, dimensions: IVec2, format: TexelFormat) -> Self {
+        assert!(data.component() == format.component(), "Failed to create image. (Data does not match texel format)");
+        assert!(data.len() == (dimensions.x * dimensions.y) as usize * format.channel_count(), "Failed to create image. (Data size does not match dimensions)");
+
         Image {
             data: data,
             dimensions: dimensions,
-            channel_count: channel_count,
+            format: format,
             inv_dimensions: Vec2::new(1.0 / dimensions.x as f32, 1.0 / dimensions.y as f32)
         }
     }
 
+    // Magenta and black checkerboard, stands in for color textures that failed to load
+    pub fn placeholder() -> Self {
+        let size = 8;
+        let data: Vec<u8> = (0..size * size).flat_map(|i| {
+            if (i % size / 4 + i / size / 4) % 2 == 0 { [255, 0, 255, 255] } else { [0, 0, 0, 255] }
+        }).collect();
+        Image::new(ImageData::U8(data), IVec2::splat(size), TexelFormat::RGBA8Srgb)
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.format.channel_count()
+    }
+
+    // Gray and gray-alpha images become RGB and RGBA, so color textures don't end up in the red channel
+    pub fn expand_gray(&mut self) {
+        let channel_count = self.channel_count();
+        if channel_count > 2 {
+            return;
+        }
+
+        fn expand<T: Copy>(data: &[T], channel_count: usize) -> Vec<T> {
+            data.chunks_exact(channel_count)
+                .flat_map(|texel| std::iter::repeat_n(texel[0], 3).chain(texel[1..].iter().copied()))
+                .collect()
+        }
+        self.data = match &self.data {
+            ImageData::U8(data) => ImageData::U8(expand(data, channel_count)),
+            ImageData::U16(data) => ImageData::U16(expand(data, channel_count)),
+            ImageData::F32(data) => ImageData::F32(expand(data, channel_count))
+        };
+        self.format = TexelFormat::from_channel_count(self.format.component(), channel_count + 2).unwrap();
+    }
+
+    pub fn flip_vertical(&mut self) {
+        let row_size = self.dimensions.x as usize * self.channel_count();
+        fn flip_rows<T: Clone>(data: &mut Vec<T>, row_size: usize) {
+            let rows: Vec<&[T]> = data.chunks_exact(row_size).rev().collect();
+            *data = rows.concat();
+        }
+
+        match &mut self.data {
+            ImageData::U8(data) => flip_rows(data, row_size),
+            ImageData::U16(data) => flip_rows(data, row_size),
+            ImageData::F32(data) => flip_rows(data, row_size)
+        }
+    }
+
+    // Single channel images and color images with equal channels
+    pub fn is_grayscale(&self) -> bool {
+        if self.channel_count() <= 2 {
+            return true;
+        }
+        (0..self.dimensions.y as usize).all(|y| (0..self.dimensions.x as usize).all(|x| {
+            let texel = self.texel(x, y);
+            texel.x == texel.y && texel.y == texel.z
+        }))
+    }
+
+    // Treats the first channel as height and encodes the slopes as a tangent space normal map,
+    // strength is the height difference between two neighbouring texels at full intensity
+    pub fn height_to_normal_map(&self, strength: f32) -> Image {
+        let width = self.dimensions.x as usize;
+        let height = self.dimensions.y as usize;
+        let sample = |x: usize, y: usize| self.texel(x % width, y % height).x;
+
+        let mut data = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                // Central differences that wrap around, +Y points to the top of the image
+                let dx = (sample(x + 1, y) - sample(x + width - 1, y)) * 0.5;
+                let dy = (sample(x, y + height - 1) - sample(x, y + 1)) * 0.5;
+                let normal = Vec3::new(-dx * strength, -dy * strength, 1.0).normalize();
+                data.extend((normal * 0.5 + 0.5).to_array().iter().map(|value| (value * 255.0 + 0.5) as u8));
+            }
+        }
+        Image::new(ImageData::U8(data), self.dimensions, TexelFormat::RGB8)
+    }
+
+    pub fn as_u8(&self) -> Option<&[u8]> {
+        match &self.data {
+            ImageData::U8(data) => Some(data),
+            _ => None
+        }
+    }
+
+    pub fn as_u16(&self) -> Option<&[u16]> {
+        match &self.data {
+            ImageData::U16(data) => Some(data),
+            _ => None
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<&[f32]> {
+        match &self.data {
+            ImageData::F32(data) => Some(data),
+            _ => None
+        }
+    }
+
     pub fn sample_pixel(&self, x: f32, y: f32, bilinear: bool) -> Vec4 {
         if bilinear {
             let tl = self.get_pixel(x - self.inv_dimensions.x, y - self.inv_dimensions.y);
             let bl = self.get_pixel(x - self.inv_dimensions.x, y + self.inv_dimensions.y);
             let br = self.get_pixel(x + self.inv_dimensions.x, y + self.inv_dimensions.y);
             let tr = self.get_pixel(x + self.inv_dimensions.x, y - self.inv_dimensions.y);
-            
+
             let r = (br + tr) * 0.5;
             let l = (bl + tl) * 0.5;
             let t = (tr + tl) * 0.5;
             let b = (br + bl) * 0.5;
-            
+
             let x = x * self.dimensions.x as f32;
             let y = y * self.dimensions.y as f32;
-            let xd = x - ((x as i32) as f32);
-            let yd = y - ((y as i32) as f32);
-            
+            let xd = x - x.floor();
+            let yd = y - y.floor();
+
             (l.lerp(r, xd) + t.lerp(b, yd)) * 0.5
         } else {
             self.get_pixel(x, y)
         }
     }
 
+    // Nearest texel lookup, coordinates outside of [0, 1] wrap around
     pub fn get_pixel(&self, x: f32, y: f32) -> Vec4 {
-        let x = ((x * self.dimensions.x as f32) as usize) % (self.dimensions.x - 1) as usize;
-        let y = ((y * self.dimensions.y as f32) as usize) % (self.dimensions.y - 1) as usize;
-
-        match self.channel_count {
-            4 => {
-                let data: &Vec<(u8, u8, u8, u8)> = unsafe { std::mem::transmute(&self.data) };
-                let pixel = &data[y * (self.dimensions.x as usize) + x];
-    
-                Vec4::new(pixel.0 as f32 / 255.99, pixel.1 as f32 / 255.99, pixel.2 as f32 / 255.99, pixel.3 as f32 / 255.99)
-            },
-            3 => {
-                let data: &Vec<(u8, u8, u8)> = unsafe { std::mem::transmute(&self.data) };
-                let pixel = &data[y * (self.dimensions.x as usize) + x];
-    
-                Vec4::new(pixel.0 as f32 / 255.99, pixel.1 as f32 / 255.99, pixel.2 as f32 / 255.99, 0.0)
+        let x = ((x * self.dimensions.x as f32).floor() as i32).rem_euclid(self.dimensions.x);
+        let y = ((y * self.dimensions.y as f32).floor() as i32).rem_euclid(self.dimensions.y);
+
+        self.texel(x as usize, y as usize)
+    }
+
+    // Returns the normalized texel value in linear space, missing channels default to (0, 0, 0, 1).
+    pub fn texel(&self, x: usize, y: usize) -> Vec4 {
+        let channel_count = self.channel_count();
+        let offset = (y * (self.dimensions.x as usize) + x) * channel_count;
+
+        let mut texel = [0.0, 0.0, 0.0, 1.0];
+        match &self.data {
+            ImageData::U8(data) => {
+                let srgb = self.format.is_srgb();
+                for (c, value) in data[offset..offset + channel_count].iter().enumerate() {
+                    texel[c] = if srgb && c < 3 { srgb_u8_to_linear(*value) } else { *value as f32 / 255.0 };
+                }
             },
-            2 => {
-                let data: &Vec<(u8, u8)> = unsafe { std::mem::transmute(&self.data) };
-                let pixel = &data[y * (self.dimensions.x as usize) + x];
-    
-                Vec4::new(pixel.0 as f32 / 255.99, pixel.1 as f32 / 255.99, 0.0, 0.0)
+            ImageData::U16(data) => {
+                for (c, value) in data[offset..offset + channel_count].iter().enumerate() {
+                    texel[c] = *value as f32 / 65535.0;
+                }
             },
-            1 => {
-                let data: &Vec<u8> = unsafe { std::mem::transmute(&self.data) };
-                let pixel = &data[y * (self.dimensions.x as usize) + x];
-    
-                Vec4::new(*pixel as f32 / 255.99, 0.0, 0.0, 0.0)
+            ImageData::F32(data) => {
+                texel[..channel_count].copy_from_slice(&data[offset..offset + channel_count]);
             }
-            _ => panic!("Failed to get pixel. (Unsupported channel count)")
+        }
+
+        Vec4::from(texel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Texels hold their own x and y coordinate in the red and green channel
+    fn coordinate_image(width: i32, height: i32) -> Image {
+        let data = (0..height).flat_map(|y| (0..width).flat_map(move |x| [x as u8, y as u8, 0])).collect();
+        Image::new(ImageData::U8(data), IVec2::new(width, height), TexelFormat::RGB8)
+    }
+
+    fn texel_at(image: &Image, x: f32, y: f32) -> (u8, u8) {
+        let pixel = image.get_pixel(x, y) * 255.0;
+        (pixel.x.round() as u8, pixel.y.round() as u8)
+    }
+
+    #[test]
+    fn get_pixel_wraps_around() {
+        let image = coordinate_image(4, 2);
+        assert_eq!(texel_at(&image, 0.0, 0.0), (0, 0));
+        assert_eq!(texel_at(&image, 0.9, 0.9), (3, 1));
+        assert_eq!(texel_at(&image, 1.1, 1.6), (0, 1));
+        assert_eq!(texel_at(&image, -0.1, -0.1), (3, 1));
+        assert_eq!(texel_at(&image, -0.8, -0.6), (0, 0));
+        assert_eq!(texel_at(&image, -2.3, 3.2), (2, 0));
+    }
+
+    #[test]
+    fn get_pixel_handles_single_texel_images() {
+        let image = coordinate_image(1, 1);
+        for (x, y) in [(0.0, 0.0), (0.5, 0.99), (1.0, 1.0), (-0.5, -3.25), (7.5, 2.0)] {
+            assert_eq!(texel_at(&image, x, y), (0, 0));
+            image.sample_pixel(x, y, true);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn height_maps_become_normal_maps() {
+        let heights = [0u8, 100, 200, 100];
+        let data = heights.iter().flat_map(|height| [*height; 3]).collect();
+        let image = Image::new(ImageData::U8(data), IVec2::new(4, 1), TexelFormat::RGB8);
+        assert!(image.is_grayscale());
+        assert!(!coordinate_image(2, 2).is_grayscale());
+
+        // The normal leans away from the uphill direction, a flat map points straight out
+        let normal_map = image.height_to_normal_map(1.0);
+        let normals = normal_map.as_u8().unwrap();
+        assert!(normals[3] < 128 && normals[4] == 128 && normals[5] > 128);
+        assert!(normals[9] > 128 && normals[10] == 128);
+
+        let flat = Image::new(ImageData::U8(vec![50; 12]), IVec2::new(4, 1), TexelFormat::RGB8).height_to_normal_map(1.0);
+        assert!(flat.as_u8().unwrap().chunks_exact(3).all(|normal| normal == [128, 128, 255]));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::glam::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    // Cone angles in radians, measured from the spot direction
+    Spot { inner_cone_angle: f32, outer_cone_angle: f32 }
+}
+
+#[derive(Clone, Debug)]
+pub struct Light {
+    pub name: String,
+    pub kind: LightKind,
+    pub color: Vec3,
+    pub intensity: f32,
+    // Distance at which point and spot lights fade out completely, infinite when None
+    pub range: Option<f32>,
+    pub position: Vec3,
+    // Direction the light is travelling in, the local -Z axis of the light node
+    pub direction: Vec3
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Light {
+            name: String::from("Directional"),
+            kind: LightKind::Directional,
+            color: color,
+            intensity: intensity,
+            range: None,
+            position: Vec3::ZERO,
+            direction: direction.normalize()
+        }
+    }
+
+    pub fn point(position: Vec3, color: Vec3, intensity: f32, range: Option<f32>) -> Self {
+        Light {
+            name: String::from("Point"),
+            kind: LightKind::Point,
+            color: color,
+            intensity: intensity,
+            range: range,
+            position: position,
+            direction: Vec3::NEG_Z
+        }
+    }
+
+    pub fn spot(position: Vec3, direction: Vec3, color: Vec3, intensity: f32, range: Option<f32>, inner_cone_angle: f32, outer_cone_angle: f32) -> Self {
+        Light {
+            name: String::from("Spot"),
+            kind: LightKind::Spot { inner_cone_angle: inner_cone_angle, outer_cone_angle: outer_cone_angle },
+            color: color,
+            intensity: intensity,
+            range: range,
+            position: position,
+            direction: direction.normalize()
+        }
+    }
+
+    // Moves the light along with the geometry it belongs to
+    pub fn transformed(&self, matrix: &Mat4) -> Self {
+        Light {
+            position: matrix.transform_point3(self.position),
+            direction: matrix.transform_vector3(self.direction).normalize_or_zero(),
+            ..self.clone()
+        }
+    }
+
+    // Returns the normalized direction towards the light and the radiance arriving at the position
+    pub fn incident(&self, position: Vec3) -> (Vec3, Vec3) {
+        match self.kind {
+            LightKind::Directional => (-self.direction, self.color * self.intensity),
+            LightKind::Point | LightKind::Spot { .. } => {
+                let to_light = self.position - position;
+                let distance_sq = to_light.length_squared().max(0.0001);
+                let l = to_light / distance_sq.sqrt();
+
+                // Range falloff as recommended by KHR_lights_punctual
+                let mut attenuation = 1.0 / distance_sq;
+                if let Some(range) = self.range {
+                    let ratio = distance_sq / (range * range);
+                    attenuation *= (1.0 - ratio * ratio).clamp(0.0, 1.0);
+                }
+
+                if let LightKind::Spot { inner_cone_angle, outer_cone_angle } = self.kind {
+                    let cos_outer = outer_cone_angle.cos();
+                    let scale = 1.0 / (inner_cone_angle.cos() - cos_outer).max(0.001);
+                    let offset = -cos_outer * scale;
+                    let cone = (self.direction.dot(-l) * scale + offset).clamp(0.0, 1.0);
+                    attenuation *= cone * cone;
+                }
+
+                (l, self.color * self.intensity * attenuation)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn point_lights_fall_off_with_range() {
+        let light = Light::point(Vec3::ZERO, Vec3::ONE, 1.0, None);
+        let (l, radiance) = light.incident(Vec3::new(2.0, 0.0, 0.0));
+        assert_eq!(l, Vec3::NEG_X);
+        assert_near(radiance.x, 0.25);
+
+        // Windowed inverse square falloff reaching zero at the range
+        let light = Light::point(Vec3::ZERO, Vec3::ONE, 1.0, Some(4.0));
+        let ratio: f32 = 4.0 / 16.0;
+        assert_near(light.incident(Vec3::new(2.0, 0.0, 0.0)).1.x, 0.25 * (1.0 - ratio * ratio));
+        assert_eq!(light.incident(Vec3::new(4.0, 0.0, 0.0)).1, Vec3::ZERO);
+        assert_eq!(light.incident(Vec3::new(0.0, 5.0, 0.0)).1, Vec3::ZERO);
+    }
+
+    #[test]
+    fn spot_lights_fade_between_the_cones() {
+        let inner = (20.0f32).to_radians();
+        let outer = (40.0f32).to_radians();
+        let light = Light::spot(Vec3::ZERO, Vec3::NEG_Y, Vec3::ONE, 1.0, None, inner, outer);
+        let at_angle = |angle: f32| light.incident(Vec3::new(angle.sin(), -angle.cos(), 0.0)).1.x;
+
+        assert_near(at_angle(0.0), 1.0);
+        assert_near(at_angle(inner), 1.0);
+        assert_near(at_angle(outer), 0.0);
+        assert_eq!(at_angle((60.0f32).to_radians()), 0.0);
+
+        // Smooth falloff from the cosine of the angle, squared
+        let angle = (30.0f32).to_radians();
+        let t = (angle.cos() - outer.cos()) / (inner.cos() - outer.cos());
+        assert_near(at_angle(angle), t * t);
+    }
+
+    #[test]
+    fn directional_lights_ignore_distance() {
+        let light = Light::directional(Vec3::new(0.0, -2.0, 0.0), Vec3::ONE, 3.0);
+        let (l, radiance) = light.incident(Vec3::new(100.0, 5.0, -7.0));
+        assert_eq!(l, Vec3::Y);
+        assert_eq!(radiance, Vec3::splat(3.0));
+    }
+
+    #[test]
+    fn lights_follow_the_model_matrix() {
+        let light = Light::spot(Vec3::X, Vec3::NEG_Z, Vec3::ONE, 1.0, None, 0.1, 0.2);
+        let matrix = Mat4::from_translation(Vec3::Y) * Mat4::from_rotation_y(std::f32::consts::FRAC_PI_2) * Mat4::from_scale(Vec3::splat(2.0));
+        let light = light.transformed(&matrix);
+
+        assert!(light.position.distance(Vec3::new(0.0, 1.0, -2.0)) < 1e-5);
+        assert!(light.direction.distance(Vec3::NEG_X) < 1e-5);
+    }
+}
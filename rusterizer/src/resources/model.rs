@@ -1,28 +1,111 @@
+use std::borrow::Cow;
+
 use crate::glam::*;
 
 use crate::resources::Image;
+use crate::resources::Light;
+use crate::resources::Camera;
+use crate::resources::{Skin, SkinningMethod, skin_vertices};
+use crate::resources::Animation;
+use crate::resources::{MorphTarget, morph_vertices};
 use crate::Shared;
 
+// Affine UV transform from KHR_texture_transform, applied as translation * rotation * scale
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureTransform {
+    pub offset: Vec2,
+    pub rotation: f32,
+    pub scale: Vec2
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        TextureTransform {
+            offset: Vec2::ZERO,
+            rotation: 0.0,
+            scale: Vec2::ONE
+        }
+    }
+}
+
+impl TextureTransform {
+    pub fn apply(&self, tex_coord: Vec2) -> Vec2 {
+        let scaled = tex_coord * self.scale;
+        let (sin, cos) = self.rotation.sin_cos();
+        Vec2::new(
+            cos * scaled.x + sin * scaled.y,
+            -sin * scaled.x + cos * scaled.y
+        ) + self.offset
+    }
+}
+
+#[derive(Clone)]
+pub struct Texture {
+    pub image: Shared<Image>,
+    // Index of the TEXCOORD set the texture is sampled with
+    pub tex_coord: usize,
+    pub transform: TextureTransform
+}
+
+impl Default for Texture {
+    fn default() -> Self {
+        Texture {
+            image: Shared::empty(),
+            tex_coord: 0,
+            transform: TextureTransform::default()
+        }
+    }
+}
+
+impl Texture {
+    pub fn new(image: Shared<Image>) -> Self {
+        Texture {
+            image: image,
+            ..Texture::default()
+        }
+    }
+
+    // Texture coordinates of the given UV sets after applying the transform
+    pub fn tex_coord(&self, tex_coord_0: Vec2, tex_coord_1: Vec2) -> Vec2 {
+        match self.tex_coord {
+            1 => self.transform.apply(tex_coord_1),
+            _ => self.transform.apply(tex_coord_0)
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Material {
     pub name: String,
     pub index: Option<usize>,
 
     pub base_color_factor: Vec4,
-    pub base_color_texture: Shared<Image>,
+    pub base_color_texture: Texture,
 
     pub normal_scale: f32,
-    pub normal_texture: Shared<Image>,
+    pub normal_texture: Texture,
 
     pub metallic_factor: f32,
     pub roughness_factor: f32,
-    pub metallic_roughness_texture: Shared<Image>,
+    pub metallic_roughness_texture: Texture,
 
     pub occlusion_strength: f32,
-    pub occlusion_texture: Shared<Image>,
+    pub occlusion_texture: Texture,
 
     pub emissive_factor: Vec3,
-    pub emissive_texture: Shared<Image>,
+    pub emissive_texture: Texture,
+    pub emissive_strength: f32,
+
+    // Shaded with only the base color, no lighting
+    pub unlit: bool,
+
+    pub clearcoat: Option<Clearcoat>,
+    pub sheen: Option<Sheen>,
+    pub specular: Specular,
+    pub ior: f32,
+    pub transmission: Option<Transmission>,
+    // Without a volume the surface is treated as thin walled
+    pub volume: Option<Volume>
 }
 
 impl Default for Material {
@@ -31,16 +114,132 @@ impl Default for Material {
             name: String::from("default"),
             index: None,
             base_color_factor: Vec4::new(1.0, 1.0, 1.0, 1.0),
-            base_color_texture: Shared::empty(),
+            base_color_texture: Texture::default(),
             normal_scale: 1.0,
-            normal_texture: Shared::empty(),
+            normal_texture: Texture::default(),
             metallic_factor: 0.0,
             roughness_factor: 1.0,
-            metallic_roughness_texture: Shared::empty(),
+            metallic_roughness_texture: Texture::default(),
             occlusion_strength: 1.0,
-            occlusion_texture: Shared::empty(),
+            occlusion_texture: Texture::default(),
             emissive_factor: Vec3::default(),
-            emissive_texture: Shared::empty(),
+            emissive_texture: Texture::default(),
+            emissive_strength: 1.0,
+            unlit: false,
+            clearcoat: None,
+            sheen: None,
+            specular: Specular::default(),
+            ior: 1.5,
+            transmission: None,
+            volume: None
+        }
+    }
+}
+
+impl Material {
+    // Used for materials that are referenced but couldn't be loaded
+    pub fn placeholder(image: Shared<Image>) -> Self {
+        Material {
+            name: String::from("placeholder"),
+            base_color_texture: Texture::new(image),
+            ..Material::default()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Clearcoat {
+    pub factor: f32,
+    pub texture: Texture,
+    pub roughness_factor: f32,
+    pub roughness_texture: Texture,
+    pub normal_scale: f32,
+    pub normal_texture: Texture
+}
+
+impl Default for Clearcoat {
+    fn default() -> Self {
+        Clearcoat {
+            factor: 0.0,
+            texture: Texture::default(),
+            roughness_factor: 0.0,
+            roughness_texture: Texture::default(),
+            normal_scale: 1.0,
+            normal_texture: Texture::default()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Sheen {
+    pub color_factor: Vec3,
+    pub color_texture: Texture,
+    pub roughness_factor: f32,
+    pub roughness_texture: Texture
+}
+
+impl Default for Sheen {
+    fn default() -> Self {
+        Sheen {
+            color_factor: Vec3::ZERO,
+            color_texture: Texture::default(),
+            roughness_factor: 0.0,
+            roughness_texture: Texture::default()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Specular {
+    pub factor: f32,
+    // Strength is stored in the alpha channel
+    pub texture: Texture,
+    pub color_factor: Vec3,
+    pub color_texture: Texture
+}
+
+impl Default for Specular {
+    fn default() -> Self {
+        Specular {
+            factor: 1.0,
+            texture: Texture::default(),
+            color_factor: Vec3::ONE,
+            color_texture: Texture::default()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Transmission {
+    pub factor: f32,
+    pub texture: Texture
+}
+
+impl Default for Transmission {
+    fn default() -> Self {
+        Transmission {
+            factor: 0.0,
+            texture: Texture::default()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Volume {
+    pub thickness_factor: f32,
+    // Thickness is stored in the green channel
+    pub thickness_texture: Texture,
+    pub attenuation_distance: f32,
+    pub attenuation_color: Vec3
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Volume {
+            thickness_factor: 0.0,
+            thickness_texture: Texture::default(),
+            attenuation_distance: f32::INFINITY,
+            attenuation_color: Vec3::ONE
         }
     }
 }
@@ -52,7 +251,10 @@ pub struct Vertex {
     pub tangent: Vec4,
     pub tex_coord: Vec2,
     pub tex_coord_1: Vec2,
-    pub color: Vec4
+    pub color: Vec4,
+    // Indices into the joints of the skin and their weights
+    pub joints: UVec4,
+    pub weights: Vec4
 }
 
 impl Default for Vertex {
@@ -63,24 +265,315 @@ impl Default for Vertex {
             tangent: Vec4::default(),
             tex_coord: Vec2::default(),
             tex_coord_1: Vec2::default(),
-            color: Vec4::default()
+            color: Vec4::ONE,
+            joints: UVec4::ZERO,
+            weights: Vec4::ZERO
         }
     }
 }
 
+// Strips, fans and loops are converted to lists while loading
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrimitiveType {
+    Triangles,
+    Lines,
+    Points
+}
+
 #[derive(Clone)]
 pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
+    pub primitive_type: PrimitiveType,
 
     pub min: Vec3,
     pub max: Vec3,
 
-    pub material_idx: usize
+    pub material_idx: usize,
+
+    pub morph_targets: Vec<MorphTarget>,
+    // Default morph target weights of the mesh
+    pub weights: Vec<f32>
+}
+
+impl Mesh {
+    // Bounds are computed from the vertices, for formats that don't store them
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>, primitive_type: PrimitiveType, material_idx: usize) -> Self {
+        let min = vertices.iter().fold(Vec3::splat(f32::MAX), |min, vertex| min.min(vertex.position));
+        let max = vertices.iter().fold(Vec3::splat(f32::MIN), |max, vertex| max.max(vertex.position));
+        Mesh {
+            vertices: vertices,
+            indices: indices,
+            primitive_type: primitive_type,
+            min: min,
+            max: max,
+            material_idx: material_idx,
+            morph_targets: Vec::new(),
+            weights: Vec::new()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Node {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+    // Morph target weights, taken from the mesh unless the node overrides them
+    pub weights: Vec<f32>,
+    // Derived from the local transforms by Model::update_world_transforms
+    pub world_matrix: Mat4,
+
+    // Indices into the meshes of the model, one per glTF primitive
+    pub meshes: Vec<usize>,
+    pub light: Option<usize>,
+    pub camera: Option<usize>,
+    pub skin: Option<usize>
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node {
+            name: String::from("Unnamed"),
+            parent: None,
+            children: Vec::new(),
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+            weights: Vec::new(),
+            world_matrix: Mat4::IDENTITY,
+            meshes: Vec::new(),
+            light: None,
+            camera: None,
+            skin: None
+        }
+    }
+}
+
+impl Node {
+    pub fn local_matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+#[derive(Clone)]
+pub struct Scene {
+    pub name: String,
+    // Root nodes of the scene
+    pub nodes: Vec<usize>
 }
 
 #[derive(Clone)]
 pub struct Model {
     pub meshes: Vec<Mesh>,
-    pub materials: Vec<Shared<Material>>
-}
\ No newline at end of file
+    pub materials: Vec<Shared<Material>>,
+    pub nodes: Vec<Node>,
+    pub scenes: Vec<Scene>,
+    // Active scene
+    pub scene: usize,
+    // World space lights, indexed by Node::light
+    pub lights: Vec<Light>,
+    // Indexed by Node::camera
+    pub cameras: Vec<Camera>,
+    pub skins: Vec<Skin>,
+    pub animations: Vec<Animation>
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MeshInstance {
+    pub node: usize,
+    pub mesh: usize,
+    // Identity for skinned meshes, their vertices are deformed into world space
+    pub world_matrix: Mat4
+}
+
+impl Model {
+    // A single root node instancing every mesh, for formats without a scene hierarchy
+    pub fn from_meshes(name: &str, meshes: Vec<Mesh>, materials: Vec<Material>) -> Self {
+        let root = Node {
+            name: String::from(name),
+            meshes: (0..meshes.len()).collect(),
+            ..Node::default()
+        };
+
+        Model {
+            meshes: meshes,
+            materials: materials.into_iter().map(|m| Shared::new(m)).collect(),
+            nodes: vec![root],
+            scenes: vec![Scene { name: String::from("Default"), nodes: vec![0] }],
+            scene: 0,
+            lights: Vec::new(),
+            cameras: Vec::new(),
+            skins: Vec::new(),
+            animations: Vec::new()
+        }
+    }
+
+    pub fn update_world_transforms(&mut self) {
+        let mut stack: Vec<(usize, Mat4)> = (0..self.nodes.len())
+            .filter(|i| self.nodes[*i].parent.is_none())
+            .map(|i| (i, Mat4::IDENTITY))
+            .collect();
+
+        while let Some((i, parent_matrix)) = stack.pop() {
+            let node = &mut self.nodes[i];
+            node.world_matrix = parent_matrix * node.local_matrix();
+
+            if let Some(light) = node.light {
+                let light = &mut self.lights[light];
+                light.position = node.world_matrix.transform_point3(Vec3::ZERO);
+                light.direction = node.world_matrix.transform_vector3(Vec3::NEG_Z).normalize_or_zero();
+            }
+
+            for child in &node.children {
+                stack.push((*child, node.world_matrix));
+            }
+        }
+    }
+
+    // All nodes reachable from the roots of the active scene
+    pub fn scene_nodes(&self) -> Vec<usize> {
+        let mut nodes = Vec::new();
+        let mut stack = match self.scenes.get(self.scene) {
+            Some(scene) => scene.nodes.clone(),
+            None => return nodes
+        };
+
+        while let Some(i) = stack.pop() {
+            nodes.push(i);
+            stack.extend(self.nodes[i].children.iter().rev());
+        }
+        nodes
+    }
+
+    // Every mesh instance in the active scene
+    pub fn instances(&self) -> Vec<MeshInstance> {
+        self.scene_nodes().into_iter()
+            .flat_map(|i| self.nodes[i].meshes.iter().map(move |mesh| MeshInstance {
+                node: i,
+                mesh: *mesh,
+                world_matrix: if self.nodes[i].skin.is_some() { Mat4::IDENTITY } else { self.nodes[i].world_matrix }
+            }))
+            .collect()
+    }
+
+    // Vertices of the instance after morphing and skinning, borrowed when there is nothing to deform
+    pub fn instance_vertices(&self, instance: &MeshInstance, skinning_method: SkinningMethod) -> Cow<'_, [Vertex]> {
+        let mesh = &self.meshes[instance.mesh];
+        let node = &self.nodes[instance.node];
+        let mut vertices = Cow::Borrowed(mesh.vertices.as_slice());
+
+        let weights = if node.weights.is_empty() { &mesh.weights } else { &node.weights };
+        if !mesh.morph_targets.is_empty() && weights.iter().any(|weight| *weight != 0.0) {
+            vertices = Cow::Owned(morph_vertices(&vertices, &mesh.morph_targets, weights));
+        }
+
+        if let Some(skin) = node.skin {
+            let joint_matrices = self.skins[skin].joint_matrices(&self.nodes);
+            vertices = Cow::Owned(skin_vertices(&vertices, &joint_matrices, skinning_method));
+        }
+        vertices
+    }
+
+    // Nodes of the active scene that carry a camera
+    pub fn scene_cameras(&self) -> Vec<usize> {
+        self.scene_nodes().into_iter()
+            .filter(|i| self.nodes[*i].camera.is_some())
+            .collect()
+    }
+
+    pub fn set_scene(&mut self, scene: usize) {
+        self.scene = scene.min(self.scenes.len().saturating_sub(1));
+    }
+
+    pub fn scene_lights(&self) -> Vec<Light> {
+        self.scene_nodes().into_iter()
+            .filter_map(|i| self.nodes[i].light)
+            .map(|light| self.lights[light].clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(translation: Vec3, children: Vec<usize>) -> Node {
+        Node {
+            name: String::from("Node"),
+            parent: None,
+            children: children,
+            translation: translation,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+            weights: Vec::new(),
+            world_matrix: Mat4::IDENTITY,
+            meshes: Vec::new(),
+            light: None,
+            camera: None,
+            skin: None
+        }
+    }
+
+    #[test]
+    fn nodes_inherit_their_parent_transform() {
+        let mut nodes = vec![node(Vec3::X, vec![1]), node(Vec3::Y, vec![]), node(Vec3::Z, vec![])];
+        nodes[0].scale = Vec3::splat(2.0);
+        nodes[1].parent = Some(0);
+        nodes[1].meshes = vec![0];
+        nodes[1].light = Some(0);
+        nodes[2].meshes = vec![0];
+
+        let mut model = Model {
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            nodes: nodes,
+            scenes: vec![Scene { name: String::from("Scene"), nodes: vec![0] }],
+            scene: 0,
+            lights: vec![Light::point(Vec3::ZERO, Vec3::ONE, 1.0, None)],
+            cameras: Vec::new(),
+            skins: Vec::new(),
+            animations: Vec::new()
+        };
+        model.update_world_transforms();
+
+        // Only the nodes of the active scene are instanced
+        let instances = model.instances();
+        assert_eq!(instances.len(), 1);
+        assert!(instances[0].world_matrix.transform_point3(Vec3::ZERO).abs_diff_eq(Vec3::new(1.0, 2.0, 0.0), 1e-6));
+
+        let lights = model.scene_lights();
+        assert_eq!(lights.len(), 1);
+        assert!(lights[0].position.abs_diff_eq(Vec3::new(1.0, 2.0, 0.0), 1e-6));
+    }
+
+    #[test]
+    fn texture_transform_matches_the_spec_example() {
+        // Example from the KHR_texture_transform specification
+        let transform = TextureTransform {
+            offset: Vec2::new(0.0, 1.0),
+            rotation: std::f32::consts::FRAC_PI_2,
+            scale: Vec2::new(0.5, 0.5)
+        };
+
+        // Scaled first, then rotated counter-clockwise with v pointing down, then offset
+        assert!(transform.apply(Vec2::new(0.0, 0.0)).abs_diff_eq(Vec2::new(0.0, 1.0), 1e-6));
+        assert!(transform.apply(Vec2::new(1.0, 0.0)).abs_diff_eq(Vec2::new(0.0, 0.5), 1e-6));
+        assert!(transform.apply(Vec2::new(0.0, 1.0)).abs_diff_eq(Vec2::new(0.5, 1.0), 1e-6));
+        assert!(transform.apply(Vec2::new(1.0, 1.0)).abs_diff_eq(Vec2::new(0.5, 0.5), 1e-6));
+    }
+
+    #[test]
+    fn textures_pick_their_uv_set() {
+        let mut texture = Texture::default();
+        assert_eq!(texture.tex_coord(Vec2::new(0.25, 0.5), Vec2::new(0.75, 1.0)), Vec2::new(0.25, 0.5));
+
+        texture.tex_coord = 1;
+        texture.transform.offset = Vec2::new(0.5, 0.0);
+        assert_eq!(texture.tex_coord(Vec2::new(0.25, 0.5), Vec2::new(0.75, 1.0)), Vec2::new(1.25, 1.0));
+    }
+}
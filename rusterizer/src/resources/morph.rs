@@ -0,0 +1,57 @@
+use crate::glam::*;
+use crate::resources::Vertex;
+
+// Per vertex displacements, attributes the target doesn't displace are left empty
+#[derive(Clone, Default)]
+pub struct MorphTarget {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub tangents: Vec<Vec3>
+}
+
+pub fn morph_vertices(vertices: &[Vertex], targets: &[MorphTarget], weights: &[f32]) -> Vec<Vertex> {
+    let mut morphed = vertices.to_vec();
+    for (target, weight) in targets.iter().zip(weights) {
+        if *weight == 0.0 {
+            continue;
+        }
+
+        for (vertex, delta) in morphed.iter_mut().zip(&target.positions) {
+            vertex.position += *delta * *weight;
+        }
+        for (vertex, delta) in morphed.iter_mut().zip(&target.normals) {
+            vertex.normal += *delta * *weight;
+        }
+        for (vertex, delta) in morphed.iter_mut().zip(&target.tangents) {
+            vertex.tangent += Vec4::from((*delta * *weight, 0.0));
+        }
+    }
+
+    for vertex in &mut morphed {
+        vertex.normal = vertex.normal.normalize_or_zero();
+        vertex.tangent = Vec4::from((vertex.tangent.xyz().normalize_or_zero(), vertex.tangent.w));
+    }
+    morphed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weighted_displacements_are_summed() {
+        let vertices = vec![Vertex { position: Vec3::ZERO, normal: Vec3::Z, ..Vertex::default() }];
+        let targets = [
+            MorphTarget { positions: vec![Vec3::X], ..MorphTarget::default() },
+            MorphTarget { positions: vec![Vec3::Y], normals: vec![Vec3::new(0.0, 1.0, -1.0)], ..MorphTarget::default() }
+        ];
+
+        let morphed = morph_vertices(&vertices, &targets, &[0.5, 1.0]);
+        assert_eq!(morphed[0].position, Vec3::new(0.5, 1.0, 0.0));
+        assert!(morphed[0].normal.abs_diff_eq(Vec3::Y, 1e-6));
+
+        let unchanged = morph_vertices(&vertices, &targets, &[0.0, 0.0]);
+        assert_eq!(unchanged[0].position, Vec3::ZERO);
+        assert_eq!(unchanged[0].normal, Vec3::Z);
+    }
+}
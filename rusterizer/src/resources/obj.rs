@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+
+use crate::glam::*;
+use crate::resources::{Mesh, PrimitiveType, Vertex, ResourceError, ResourceResult, generate_tangents, polygon_normal, triangulate_polygon};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObjCorner {
+    pub position: usize,
+    pub tex_coord: Option<usize>,
+    pub normal: Option<usize>
+}
+
+#[derive(Clone, Debug)]
+pub struct ObjFace {
+    pub corners: Vec<ObjCorner>,
+    // 0 when smoothing is off, faces in the same group share normals at their corners
+    pub smoothing_group: u32
+}
+
+// Faces between two `o`, `g` or `usemtl` statements
+#[derive(Clone, Debug, Default)]
+pub struct ObjGroup {
+    pub name: String,
+    pub material: Option<String>,
+    pub faces: Vec<ObjFace>
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ObjData {
+    pub positions: Vec<Vec3>,
+    // Empty unless the file stores a color after every position
+    pub colors: Vec<Vec3>,
+    pub tex_coords: Vec<Vec2>,
+    pub normals: Vec<Vec3>,
+    pub groups: Vec<ObjGroup>,
+    pub material_libraries: Vec<String>
+}
+
+#[derive(Clone, Debug)]
+pub struct MtlTexture {
+    pub path: String,
+    pub offset: Vec2,
+    pub scale: Vec2,
+    pub bump_multiplier: f32
+}
+
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub diffuse: Vec3,
+    pub emissive: Vec3,
+    pub shininess: Option<f32>,
+    pub dissolve: f32,
+    pub ior: Option<f32>,
+    // From the PBR extension of the format
+    pub roughness: Option<f32>,
+    pub metallic: Option<f32>,
+    pub diffuse_texture: Option<MtlTexture>,
+    // Classic bump maps hold heights, norm maps hold tangent space normals
+    pub bump_texture: Option<MtlTexture>,
+    pub normal_texture: Option<MtlTexture>,
+    pub emissive_texture: Option<MtlTexture>
+}
+
+impl MtlMaterial {
+    fn new(name: &str) -> Self {
+        MtlMaterial {
+            name: String::from(name),
+            diffuse: Vec3::ONE,
+            emissive: Vec3::ZERO,
+            shininess: None,
+            dissolve: 1.0,
+            ior: None,
+            roughness: None,
+            metallic: None,
+            diffuse_texture: None,
+            bump_texture: None,
+            normal_texture: None,
+            emissive_texture: None
+        }
+    }
+}
+
+// Joins lines ending in a backslash and strips comments
+fn logical_lines(source: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut start = 0;
+    for (i, line) in source.lines().enumerate() {
+        if current.is_empty() {
+            start = i + 1;
+        }
+        let line = line.split('#').next().unwrap_or("");
+        match line.trim_end().strip_suffix('\\') {
+            Some(continued) => {
+                current.push_str(continued);
+                current.push(' ');
+            },
+            None => {
+                current.push_str(line);
+                lines.push((start, current.trim().to_string()));
+                current = String::new();
+            }
+        }
+    }
+    if !current.is_empty() {
+        lines.push((start, current.trim().to_string()));
+    }
+    lines
+}
+
+fn parse_floats(arguments: &[&str], line: usize) -> ResourceResult<Vec<f32>> {
+    arguments.iter().map(|argument| {
+        argument.parse::<f32>().map_err(|_| ResourceError::decode(format!("Invalid number on line {}", line)))
+    }).collect()
+}
+
+fn parse_vec3(arguments: &[&str], line: usize) -> ResourceResult<Vec3> {
+    let values = parse_floats(arguments, line)?;
+    if values.len() < 3 {
+        return Err(ResourceError::decode(format!("Expected 3 components on line {}", line)));
+    }
+    Ok(Vec3::new(values[0], values[1], values[2]))
+}
+
+// Indices start at 1, negative indices count back from the last element
+fn resolve_index(index: &str, count: usize, line: usize) -> ResourceResult<usize> {
+    let index = index.parse::<i64>().map_err(|_| ResourceError::decode(format!("Invalid index on line {}", line)))?;
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(ResourceError::validation(format!("Index out of range on line {}", line)));
+    }
+    Ok(resolved as usize)
+}
+
+pub fn parse_obj(source: &str) -> ResourceResult<ObjData> {
+    let mut data = ObjData::default();
+    let mut group = ObjGroup::default();
+    let mut smoothing_group = 0;
+
+    for (line, statement) in logical_lines(source) {
+        let mut tokens = statement.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                data.positions.push(parse_vec3(&arguments, line)?);
+                if arguments.len() >= 6 {
+                    data.colors.push(parse_vec3(&arguments[3..], line)?);
+                }
+            },
+            "vt" => {
+                let values = parse_floats(&arguments, line)?;
+                data.tex_coords.push(Vec2::new(values.first().copied().unwrap_or(0.0), values.get(1).copied().unwrap_or(0.0)));
+            },
+            "vn" => data.normals.push(parse_vec3(&arguments, line)?),
+            "f" => {
+                let corners = arguments.iter().map(|corner| {
+                    let mut parts = corner.split('/');
+                    let position = resolve_index(parts.next().unwrap_or(""), data.positions.len(), line)?;
+                    let tex_coord = parts.next().filter(|part| !part.is_empty()).map(|part| resolve_index(part, data.tex_coords.len(), line)).transpose()?;
+                    let normal = parts.next().filter(|part| !part.is_empty()).map(|part| resolve_index(part, data.normals.len(), line)).transpose()?;
+                    Ok(ObjCorner { position: position, tex_coord: tex_coord, normal: normal })
+                }).collect::<ResourceResult<Vec<_>>>()?;
+
+                if corners.len() >= 3 {
+                    group.faces.push(ObjFace { corners: corners, smoothing_group: smoothing_group });
+                }
+            },
+            "o" | "g" => {
+                let name = arguments.join(" ");
+                let material = group.material.clone();
+                if !group.faces.is_empty() {
+                    data.groups.push(std::mem::take(&mut group));
+                }
+                group.name = name;
+                group.material = material;
+            },
+            "usemtl" => {
+                let name = group.name.clone();
+                if !group.faces.is_empty() {
+                    data.groups.push(std::mem::take(&mut group));
+                }
+                group.name = name;
+                group.material = Some(arguments.join(" "));
+            },
+            "s" => {
+                smoothing_group = match arguments.first() {
+                    Some(&"off") | None => 0,
+                    Some(value) => value.parse().unwrap_or(0)
+                };
+            },
+            "mtllib" => data.material_libraries.extend(arguments.iter().map(|library| library.to_string())),
+            _ => {}
+        }
+    }
+
+    if !group.faces.is_empty() {
+        data.groups.push(group);
+    }
+    if data.colors.len() != data.positions.len() {
+        data.colors.clear();
+    }
+    Ok(data)
+}
+
+// Options come before the file name, which may contain spaces
+fn parse_mtl_texture(arguments: &[&str], line: usize) -> ResourceResult<MtlTexture> {
+    let mut texture = MtlTexture {
+        path: String::new(),
+        offset: Vec2::ZERO,
+        scale: Vec2::ONE,
+        bump_multiplier: 1.0
+    };
+
+    let mut i = 0;
+    while i < arguments.len() && arguments[i].starts_with('-') {
+        let option = arguments[i];
+        let values: Vec<f32> = arguments[i + 1..].iter().map_while(|argument| argument.parse::<f32>().ok()).collect();
+        let count = match option {
+            "-o" | "-s" | "-t" => values.len().min(3),
+            "-mm" => 2,
+            _ => 1
+        };
+
+        match option {
+            "-o" => texture.offset = Vec2::new(values.first().copied().unwrap_or(0.0), values.get(1).copied().unwrap_or(0.0)),
+            "-s" => texture.scale = Vec2::new(values.first().copied().unwrap_or(1.0), values.get(1).copied().unwrap_or(1.0)),
+            "-bm" => texture.bump_multiplier = values.first().copied().unwrap_or(1.0),
+            _ => {}
+        }
+        i += 1 + count;
+    }
+
+    if i >= arguments.len() {
+        return Err(ResourceError::decode(format!("Missing texture path on line {}", line)));
+    }
+    texture.path = arguments[i..].join(" ").replace('\\', "/");
+    Ok(texture)
+}
+
+pub fn parse_mtl(source: &str) -> ResourceResult<Vec<MtlMaterial>> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for (line, statement) in logical_lines(source) {
+        let mut tokens = statement.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            materials.push(MtlMaterial::new(&arguments.join(" ")));
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue
+        };
+        let scalar = || -> ResourceResult<f32> {
+            parse_floats(&arguments, line)?.first().copied().ok_or_else(|| ResourceError::decode(format!("Missing value on line {}", line)))
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = parse_vec3(&arguments, line)?,
+            "Ke" => material.emissive = parse_vec3(&arguments, line)?,
+            "Ns" => material.shininess = Some(scalar()?),
+            "d" => material.dissolve = scalar()?,
+            "Tr" => material.dissolve = 1.0 - scalar()?,
+            "Ni" => material.ior = Some(scalar()?),
+            "Pr" => material.roughness = Some(scalar()?),
+            "Pm" => material.metallic = Some(scalar()?),
+            "map_Kd" => material.diffuse_texture = Some(parse_mtl_texture(&arguments, line)?),
+            "map_Bump" | "map_bump" | "bump" => material.bump_texture = Some(parse_mtl_texture(&arguments, line)?),
+            "norm" => material.normal_texture = Some(parse_mtl_texture(&arguments, line)?),
+            "map_Ke" => material.emissive_texture = Some(parse_mtl_texture(&arguments, line)?),
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+// Corners that don't reference a normal are keyed by smoothing group, or by face when smoothing is off
+fn smoothing_key(face: &ObjFace, face_index: usize) -> u64 {
+    if face.smoothing_group == 0 {
+        (1 << 32) + face_index as u64
+    } else {
+        face.smoothing_group as u64
+    }
+}
+
+// Texture coordinates are flipped to the top left origin used by glTF
+pub fn build_obj_mesh(data: &ObjData, group: &ObjGroup, material_idx: usize) -> Mesh {
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+    let mut lookup: HashMap<(ObjCorner, u64), u32> = HashMap::new();
+    // Summed face normals per position and smoothing key, for corners without normals
+    let mut smoothed: HashMap<(usize, u64), Vec3> = HashMap::new();
+    let mut generated: Vec<(u32, usize, u64)> = Vec::new();
+
+    for (face_index, face) in group.faces.iter().enumerate() {
+        let points: Vec<Vec3> = face.corners.iter().map(|corner| data.positions[corner.position]).collect();
+        let face_normal = polygon_normal(&points);
+        let key = smoothing_key(face, face_index);
+
+        let corners: Vec<u32> = face.corners.iter().map(|corner| {
+            let key = if corner.normal.is_some() { 0 } else { key };
+            *lookup.entry((*corner, key)).or_insert_with(|| {
+                let index = vertices.len() as u32;
+                let tex_coord = corner.tex_coord.map(|i| data.tex_coords[i]).unwrap_or(Vec2::ZERO);
+                vertices.push(Vertex {
+                    position: data.positions[corner.position],
+                    normal: corner.normal.map(|i| data.normals[i].normalize_or_zero()).unwrap_or(Vec3::ZERO),
+                    tex_coord: Vec2::new(tex_coord.x, 1.0 - tex_coord.y),
+                    color: data.colors.get(corner.position).map(|color| Vec4::from((*color, 1.0))).unwrap_or(Vec4::ONE),
+                    ..Vertex::default()
+                });
+                if corner.normal.is_none() {
+                    generated.push((index, corner.position, key));
+                }
+                index
+            })
+        }).collect();
+
+        for corner in &face.corners {
+            if corner.normal.is_none() {
+                *smoothed.entry((corner.position, key)).or_insert(Vec3::ZERO) += face_normal;
+            }
+        }
+
+        indices.extend(triangulate_polygon(&points).into_iter().map(|i| corners[i as usize]));
+    }
+
+    for (index, position, key) in generated {
+        vertices[index as usize].normal = smoothed[&(position, key)].normalize_or_zero();
+    }
+
+    generate_tangents(&mut vertices, &mut indices);
+    Mesh::new(vertices, indices, PrimitiveType::Triangles, material_idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two quads folded along the x axis, sharing the edge between them
+    const FOLD: &str = "
+        mtllib fold.mtl
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        v 0 -1 1 \\
+          # Continued on the next line
+        v 1 -1 1
+        g fold
+        usemtl red
+        s 1
+        f 1 2 3 4
+        f 2 1 -2 -1
+    ";
+
+    #[test]
+    fn smoothing_groups_share_normals() {
+        let data = parse_obj(FOLD).unwrap();
+        assert_eq!(data.positions.len(), 6);
+        assert_eq!(data.material_libraries, vec![String::from("fold.mtl")]);
+        assert_eq!(data.groups.len(), 1);
+        assert_eq!(data.groups[0].material.as_deref(), Some("red"));
+
+        let smooth = build_obj_mesh(&data, &data.groups[0], 0);
+        assert_eq!(smooth.vertices.len(), 6);
+        assert_eq!(smooth.indices.len(), 12);
+        // Area weighted average of both faces on the shared edge
+        let shared = smooth.vertices.iter().find(|vertex| vertex.position == Vec3::ZERO).unwrap();
+        assert!(shared.normal.abs_diff_eq(Vec3::new(0.0, 1.0, 2.0).normalize(), 1e-5));
+
+        let mut flat_data = data.clone();
+        for face in &mut flat_data.groups[0].faces {
+            face.smoothing_group = 0;
+        }
+        let flat = build_obj_mesh(&flat_data, &flat_data.groups[0], 0);
+        assert_eq!(flat.vertices.len(), 8);
+        assert!(flat.vertices.iter().all(|vertex| (vertex.normal.length() - 1.0).abs() < 1e-5));
+    }
+
+    #[test]
+    fn mtl_materials_and_texture_options() {
+        let materials = parse_mtl("
+            newmtl red
+            Kd 1 0 0
+            d 0.5
+            map_Kd -s 2 2 1 -o 0.5 0 0 textures\\red diffuse.png
+            map_Bump -bm 0.25 height.png
+            newmtl blue
+            norm normal.png
+            Kd 0 0 1
+        ").unwrap();
+        assert_eq!(materials.len(), 2);
+        assert_eq!(materials[0].diffuse, Vec3::X);
+        assert_eq!(materials[0].dissolve, 0.5);
+
+        let diffuse = materials[0].diffuse_texture.as_ref().unwrap();
+        assert_eq!(diffuse.path, "textures/red diffuse.png");
+        assert_eq!(diffuse.scale, Vec2::splat(2.0));
+        assert_eq!(diffuse.offset, Vec2::new(0.5, 0.0));
+        assert_eq!(materials[0].bump_texture.as_ref().unwrap().bump_multiplier, 0.25);
+        assert!(materials[1].diffuse_texture.is_none());
+        assert!(materials[1].bump_texture.is_none());
+        assert_eq!(materials[1].normal_texture.as_ref().unwrap().path, "normal.png");
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        assert!(matches!(parse_obj("v 0 0\n"), Err(ResourceError::Decode { .. })));
+        assert!(matches!(parse_obj("v 0 0 0\nf 1 2 3\n"), Err(ResourceError::Validation { .. })));
+        assert!(matches!(parse_mtl("newmtl a\nmap_Kd -bm 2\n"), Err(ResourceError::Decode { .. })));
+    }
+}
@@ -0,0 +1,311 @@
+use crate::glam::*;
+use crate::color::srgb_to_linear_v3;
+use crate::resources::{Mesh, PrimitiveType, Vertex, NormalGeneration, ResourceError, ResourceResult, generate_flat_normals, generate_smooth_normals, generate_tangents, triangulate_polygon};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PlyType {
+    I8, U8, I16, U16, I32, U32, F32, F64
+}
+
+impl PlyType {
+    fn parse(name: &str) -> ResourceResult<Self> {
+        match name {
+            "char" | "int8" => Ok(PlyType::I8),
+            "uchar" | "uint8" => Ok(PlyType::U8),
+            "short" | "int16" => Ok(PlyType::I16),
+            "ushort" | "uint16" => Ok(PlyType::U16),
+            "int" | "int32" => Ok(PlyType::I32),
+            "uint" | "uint32" => Ok(PlyType::U32),
+            "float" | "float32" => Ok(PlyType::F32),
+            "double" | "float64" => Ok(PlyType::F64),
+            _ => Err(ResourceError::unsupported(format!("Property type {}", name)))
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8
+        }
+    }
+
+    // Integer colors use the full range of their type
+    fn normalize(&self, value: f64) -> f32 {
+        let max = match self {
+            PlyType::I8 => i8::MAX as f64,
+            PlyType::U8 => u8::MAX as f64,
+            PlyType::I16 => i16::MAX as f64,
+            PlyType::U16 => u16::MAX as f64,
+            PlyType::I32 => i32::MAX as f64,
+            PlyType::U32 => u32::MAX as f64,
+            PlyType::F32 | PlyType::F64 => 1.0
+        };
+        (value / max) as f32
+    }
+}
+
+#[derive(Clone, Debug)]
+enum PlyProperty {
+    Scalar { name: String, kind: PlyType },
+    List { name: String, count: PlyType, item: PlyType }
+}
+
+impl PlyProperty {
+    fn name(&self) -> &str {
+        match self {
+            PlyProperty::Scalar { name, .. } => name,
+            PlyProperty::List { name, .. } => name
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>
+}
+
+struct PlyReader<'a> {
+    format: PlyFormat,
+    bytes: &'a [u8],
+    offset: usize
+}
+
+impl<'a> PlyReader<'a> {
+    fn next_token(&mut self) -> ResourceResult<&'a str> {
+        while self.offset < self.bytes.len() && self.bytes[self.offset].is_ascii_whitespace() {
+            self.offset += 1;
+        }
+        let start = self.offset;
+        while self.offset < self.bytes.len() && !self.bytes[self.offset].is_ascii_whitespace() {
+            self.offset += 1;
+        }
+        if start == self.offset {
+            return Err(ResourceError::decode("Unexpected end of file"));
+        }
+        std::str::from_utf8(&self.bytes[start..self.offset]).map_err(|_| ResourceError::decode("Invalid text"))
+    }
+
+    fn read(&mut self, kind: PlyType) -> ResourceResult<f64> {
+        if self.format == PlyFormat::Ascii {
+            return self.next_token()?.parse::<f64>().map_err(|_| ResourceError::decode("Invalid number"));
+        }
+
+        let size = kind.size();
+        if self.offset + size > self.bytes.len() {
+            return Err(ResourceError::decode("Unexpected end of file"));
+        }
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(&self.bytes[self.offset..self.offset + size]);
+        self.offset += size;
+        if self.format == PlyFormat::BinaryBigEndian {
+            raw[..size].reverse();
+        }
+
+        Ok(match kind {
+            PlyType::I8 => raw[0] as i8 as f64,
+            PlyType::U8 => raw[0] as f64,
+            PlyType::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+            PlyType::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+            PlyType::I32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            PlyType::U32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            PlyType::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            PlyType::F64 => f64::from_le_bytes(raw)
+        })
+    }
+}
+
+// Returns the elements and the offset of the data after the header
+fn parse_header(bytes: &[u8]) -> ResourceResult<(PlyFormat, Vec<PlyElement>, usize)> {
+    let end = bytes.windows(10).position(|window| window == b"end_header")
+        .ok_or_else(|| ResourceError::decode("Missing end_header"))?;
+    let mut data_start = end + 10;
+    while data_start < bytes.len() && bytes[data_start] != b'\n' {
+        data_start += 1;
+    }
+    let header = std::str::from_utf8(&bytes[..end]).map_err(|_| ResourceError::decode("Invalid header"))?;
+
+    let mut lines = header.lines();
+    if lines.next().map(|line| line.trim()) != Some("ply") {
+        return Err(ResourceError::decode("Missing magic number"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", "ascii", ..] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", ..] => format = Some(PlyFormat::BinaryLittleEndian),
+            ["format", "binary_big_endian", ..] => format = Some(PlyFormat::BinaryBigEndian),
+            ["format", other, ..] => return Err(ResourceError::unsupported(format!("Format {}", other))),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| ResourceError::decode("Invalid element count"))?,
+                properties: Vec::new()
+            }),
+            ["property", "list", count, item, name] => {
+                let element = elements.last_mut().ok_or_else(|| ResourceError::decode("Property outside of an element"))?;
+                element.properties.push(PlyProperty::List { name: name.to_string(), count: PlyType::parse(count)?, item: PlyType::parse(item)? });
+            },
+            ["property", kind, name] => {
+                let element = elements.last_mut().ok_or_else(|| ResourceError::decode("Property outside of an element"))?;
+                element.properties.push(PlyProperty::Scalar { name: name.to_string(), kind: PlyType::parse(kind)? });
+            },
+            _ => {}
+        }
+    }
+
+    let format = format.ok_or_else(|| ResourceError::decode("Missing format"))?;
+    Ok((format, elements, (data_start + 1).min(bytes.len())))
+}
+
+// Faces are triangulated, files without faces are loaded as point clouds
+pub fn parse_ply(bytes: &[u8], normal_generation: NormalGeneration) -> ResourceResult<Mesh> {
+    let (format, elements, data_start) = parse_header(bytes)?;
+    let mut reader = PlyReader {
+        format: format,
+        bytes: bytes,
+        offset: data_start
+    };
+
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut faces: Vec<Vec<u32>> = Vec::new();
+    let mut has_normals = false;
+    let mut has_tex_coords = false;
+
+    for element in &elements {
+        for _ in 0..element.count {
+            let mut vertex = Vertex::default();
+            let mut color = Vec3::ONE;
+            for property in &element.properties {
+                match property {
+                    PlyProperty::Scalar { name, kind } => {
+                        let value = reader.read(*kind)?;
+                        if element.name != "vertex" {
+                            continue;
+                        }
+                        match name.as_str() {
+                            "x" => vertex.position.x = value as f32,
+                            "y" => vertex.position.y = value as f32,
+                            "z" => vertex.position.z = value as f32,
+                            "nx" => vertex.normal.x = value as f32,
+                            "ny" => vertex.normal.y = value as f32,
+                            "nz" => vertex.normal.z = value as f32,
+                            "red" | "r" => color.x = kind.normalize(value),
+                            "green" | "g" => color.y = kind.normalize(value),
+                            "blue" | "b" => color.z = kind.normalize(value),
+                            "alpha" | "a" => vertex.color.w = kind.normalize(value),
+                            "u" | "s" | "texture_u" => vertex.tex_coord.x = value as f32,
+                            "v" | "t" | "texture_v" => vertex.tex_coord.y = value as f32,
+                            _ => {}
+                        }
+                    },
+                    PlyProperty::List { name, count, item } => {
+                        let count = reader.read(*count)? as usize;
+                        let values: Vec<u32> = (0..count).map(|_| reader.read(*item).map(|value| value as u32)).collect::<ResourceResult<_>>()?;
+                        if element.name == "face" && (name == "vertex_indices" || name == "vertex_index") {
+                            faces.push(values);
+                        }
+                    }
+                }
+            }
+
+            if element.name == "vertex" {
+                // Scanners store display referred colors
+                vertex.color = Vec4::from((srgb_to_linear_v3(color), vertex.color.w));
+                vertices.push(vertex);
+            }
+        }
+
+        if element.name == "vertex" {
+            has_normals = element.properties.iter().any(|property| property.name() == "nx");
+            has_tex_coords = element.properties.iter().any(|property| matches!(property.name(), "u" | "s" | "texture_u"));
+        }
+    }
+
+    if faces.is_empty() {
+        let indices = (0..vertices.len() as u32).collect();
+        return Ok(Mesh::new(vertices, indices, PrimitiveType::Points, 0));
+    }
+
+    let mut indices: Vec<u32> = Vec::new();
+    for face in &faces {
+        if face.iter().any(|index| *index as usize >= vertices.len()) {
+            return Err(ResourceError::validation("Face index out of range"));
+        }
+        let points: Vec<Vec3> = face.iter().map(|index| vertices[*index as usize].position).collect();
+        indices.extend(triangulate_polygon(&points).into_iter().map(|i| face[i as usize]));
+    }
+
+    if has_normals {
+        for vertex in &mut vertices {
+            vertex.normal = vertex.normal.normalize_or_zero();
+        }
+    } else {
+        match normal_generation {
+            NormalGeneration::Flat => { generate_flat_normals(&mut vertices, &mut indices); },
+            NormalGeneration::Smooth => generate_smooth_normals(&mut vertices, &indices)
+        }
+    }
+    if has_tex_coords {
+        for vertex in &mut vertices {
+            vertex.tex_coord.y = 1.0 - vertex.tex_coord.y;
+        }
+        generate_tangents(&mut vertices, &mut indices);
+    }
+
+    Ok(Mesh::new(vertices, indices, PrimitiveType::Triangles, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_and_binary_match() {
+        let ascii = "ply\nformat ascii 1.0\ncomment quad\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0 255 0 0\n1 0 0 255 0 0\n1 1 0 255 0 0\n0 1 0 255 0 0\n4 0 1 2 3\n";
+
+        let mut binary = b"ply\nformat binary_big_endian 1.0\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n".to_vec();
+        for position in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]] {
+            for component in position {
+                binary.extend_from_slice(&component.to_be_bytes());
+            }
+            binary.extend_from_slice(&[255, 0, 0]);
+        }
+        binary.push(4);
+        for index in [0i32, 1, 2, 3] {
+            binary.extend_from_slice(&index.to_be_bytes());
+        }
+
+        for mesh in [parse_ply(ascii.as_bytes(), NormalGeneration::Smooth).unwrap(), parse_ply(&binary, NormalGeneration::Smooth).unwrap()] {
+            assert_eq!(mesh.primitive_type, PrimitiveType::Triangles);
+            assert_eq!(mesh.vertices.len(), 4);
+            assert_eq!(mesh.indices.len(), 6);
+            assert_eq!(mesh.max, Vec3::new(1.0, 1.0, 0.0));
+            for vertex in &mesh.vertices {
+                assert!(vertex.color.abs_diff_eq(Vec4::new(1.0, 0.0, 0.0, 1.0), 1e-5));
+                assert!(vertex.normal.abs_diff_eq(Vec3::Z, 1e-5));
+            }
+        }
+    }
+
+    #[test]
+    fn point_clouds_without_faces() {
+        let ascii = "ply\nformat ascii 1.0\nelement vertex 2\nproperty double x\nproperty double y\nproperty double z\nend_header\n0 0 0\n1 2 3\n";
+        let mesh = parse_ply(ascii.as_bytes(), NormalGeneration::Flat).unwrap();
+        assert_eq!(mesh.primitive_type, PrimitiveType::Points);
+        assert_eq!(mesh.indices, vec![0, 1]);
+        assert_eq!(mesh.max, Vec3::new(1.0, 2.0, 3.0));
+    }
+}
@@ -1,10 +1,8 @@
 extern crate gltf;
-extern crate stb_image;
 extern crate bitmask_enum;
 use bitmask_enum::bitmask;
 
 use std::fs;
-use std::ffi::CString;
 use std::path::Path;
 
 use crate::glam::*;
@@ -13,15 +11,55 @@ use crate::Shared;
 pub mod resource_manager;
 pub use resource_manager::*;
 
+pub mod error;
+pub use error::*;
+
 pub mod model;
 pub use model::*;
 
 pub mod image;
 pub use image::*;
 
+pub mod light;
+pub use light::*;
+
+pub mod geometry;
+pub use geometry::*;
+
+pub mod camera;
+pub use camera::*;
+
+pub mod skinning;
+pub use skinning::*;
+
+pub mod animation;
+pub use animation::*;
+
+pub mod morph;
+pub use morph::*;
+
+pub mod obj;
+pub use obj::*;
+
+pub mod ply;
+pub use ply::*;
+
+pub mod stl;
+pub use stl::*;
+
 #[bitmask(u8)]
 pub enum ImageImportSettings {
-    FlipVertical
+    // Images are stored top row first, glTF and OBJ texture coordinates expect that order
+    FlipVertical,
+    Srgb
+}
+
+// The parts of an imported glTF document that every mesh is processed against
+struct GltfSource<'a> {
+    document: &'a gltf::Document,
+    buffers: &'a [gltf::buffer::Data],
+    images: &'a [Option<gltf::image::Data>],
+    base_path: &'a String
 }
 
 pub struct Resources {
@@ -29,7 +67,12 @@ pub struct Resources {
     text_manager: ResourceManager<String>,
     image_manager: ResourceManager<Image>,
 
-    pub kill_time: f32
+    pub kill_time: f32,
+    // Used for meshes without normals, glTF requires flat normals in that case
+    pub normal_generation: NormalGeneration,
+    // Non-fatal failures, the affected textures and materials were replaced by placeholders
+    pub warnings: Vec<ResourceError>,
+    placeholder_image: Shared<Image>
 }
 
 impl Resources {
@@ -38,7 +81,10 @@ impl Resources {
             model_manager: ResourceManager::new(5.0),
             text_manager: ResourceManager::new(5.0),
             image_manager: ResourceManager::new(5.0),
-            kill_time: 5.0
+            kill_time: 5.0,
+            normal_generation: NormalGeneration::Flat,
+            warnings: Vec::new(),
+            placeholder_image: Shared::new(Image::placeholder())
         })
     }
 
@@ -48,286 +94,1192 @@ impl Resources {
         self.image_manager.update();
     }
 
-    fn process_tex(&mut self, texture: &gltf::Texture, base_path: &String) -> Shared<Image> {
+    // Color textures are replaced by a checkerboard, data textures are left out so they don't affect shading
+    fn placeholder(&self, import_settings: ImageImportSettings) -> Shared<Image> {
+        if import_settings.contains(ImageImportSettings::Srgb) {
+            self.placeholder_image.clone()
+        } else {
+            Shared::empty()
+        }
+    }
+
+    fn get_image_or_placeholder(&mut self, asset_path: String, import_settings: ImageImportSettings) -> Shared<Image> {
+        match self.try_get_image(asset_path, Some(import_settings)) {
+            Ok(image) => image,
+            Err(error) => {
+                self.warnings.push(error);
+                self.placeholder(import_settings)
+            }
+        }
+    }
+
+    fn process_tex(&mut self, texture: &gltf::Texture, images: &[Option<gltf::image::Data>], base_path: &String, import_settings: ImageImportSettings) -> Shared<Image> {
         let img = texture.source();
-        let img = match img.source() {
-            gltf::image::Source::Uri { uri, .. } => {
+        let file_path = match img.source() {
+            gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
                 let base_path = Path::new(base_path);
-                let path = base_path.parent().unwrap_or_else(|| Path::new("./")).join(uri);
-                self.get_image(path.into_os_string().into_string().unwrap(), Some(ImageImportSettings::FlipVertical))
+                Some(base_path.parent().unwrap_or_else(|| Path::new("./")).join(uri))
+            },
+            _ => None
+        };
+
+        match file_path {
+            Some(path) if path.exists() => {
+                self.get_image_or_placeholder(path.to_string_lossy().into_owned(), import_settings)
+            },
+            // Buffer views, data URIs and percent-encoded paths were already decoded by the importer
+            _ => self.get_embedded_image(images, img.index(), base_path, import_settings)
+        }
+    }
+
+    // Images that failed to import were already reported while loading the document
+    fn get_embedded_image(&mut self, images: &[Option<gltf::image::Data>], index: usize, base_path: &String, import_settings: ImageImportSettings) -> Shared<Image> {
+        // The same image can be used as color and as data, so the settings are part of the key
+        let key = Self::image_key(&format!("{}#image{}", base_path, index), import_settings);
+        if let Some(resource) = self.image_manager.get(&key) {
+            return resource;
+        }
+
+        let data = match images.get(index).and_then(|data| data.as_ref()) {
+            Some(data) => data,
+            None => return self.placeholder(import_settings)
+        };
+        let dimensions = IVec2::new(data.width as i32, data.height as i32);
+        let (image_data, component) = match data.format {
+            gltf::image::Format::R8 | gltf::image::Format::R8G8 | gltf::image::Format::R8G8B8 | gltf::image::Format::R8G8B8A8 => {
+                (ImageData::U8(data.pixels.clone()), TexelComponent::U8)
+            },
+            gltf::image::Format::R16 | gltf::image::Format::R16G16 | gltf::image::Format::R16G16B16 | gltf::image::Format::R16G16B16A16 => {
+                let pixels = data.pixels.chunks_exact(2).map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]])).collect();
+                (ImageData::U16(pixels), TexelComponent::U16)
+            },
+            gltf::image::Format::R32G32B32FLOAT | gltf::image::Format::R32G32B32A32FLOAT => {
+                let pixels = data.pixels.chunks_exact(4).map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect();
+                (ImageData::F32(pixels), TexelComponent::F32)
+            }
+        };
+        let channel_count = image_data.len() / (data.width as usize * data.height as usize);
+        let format = match TexelFormat::from_channel_count(component, channel_count) {
+            Some(format) => format,
+            None => {
+                self.warnings.push(ResourceError::unsupported(format!("{} channel image", channel_count)).with_path(&key));
+                return self.placeholder(import_settings);
+            }
+        };
+        let mut image = Image::new(image_data, dimensions, format);
+        image.expand_gray();
+
+        if import_settings.contains(ImageImportSettings::FlipVertical) {
+            image.flip_vertical();
+        }
+        if import_settings.contains(ImageImportSettings::Srgb) {
+            image.format = image.format.to_srgb();
+        }
+
+        let resource = Shared::new(image);
+        self.image_manager.insert(resource.clone(), key);
+        resource
+    }
+
+    fn process_tex_info(&mut self, info: &gltf::texture::Info, images: &[Option<gltf::image::Data>], base_path: &String, import_settings: ImageImportSettings) -> Texture {
+        let mut texture = Texture {
+            image: self.process_tex(&info.texture(), images, base_path, import_settings),
+            tex_coord: info.tex_coord() as usize,
+            transform: TextureTransform::default()
+        };
+
+        if let Some(transform) = info.texture_transform() {
+            texture.transform = TextureTransform {
+                offset: Vec2::from(transform.offset()),
+                rotation: transform.rotation(),
+                scale: Vec2::from(transform.scale())
+            };
+            if let Some(tex_coord) = transform.tex_coord() {
+                texture.tex_coord = tex_coord as usize;
             }
-            _ => panic!("Failed to process tex. (Only uri support)")
+        }
+        texture
+    }
+
+    // Texture info of extensions the gltf crate doesn't model, resolved by texture index
+    fn process_tex_json(&mut self, document: &gltf::Document, info: Option<&gltf::json::Value>, images: &[Option<gltf::image::Data>], base_path: &String, import_settings: ImageImportSettings) -> Texture {
+        let info = match info {
+            Some(info) => info,
+            None => return Texture::default()
         };
-        img
+        let image = info.get("index")
+            .and_then(|index| index.as_u64())
+            .and_then(|index| document.textures().nth(index as usize));
+
+        match image {
+            Some(image) => {
+                let mut texture = Texture {
+                    image: self.process_tex(&image, images, base_path, import_settings),
+                    tex_coord: info.get("texCoord").and_then(|v| v.as_u64()).unwrap_or(0) as usize,
+                    transform: TextureTransform::default()
+                };
+                Self::process_tex_transform_json(&mut texture, info.get("extensions").and_then(|extensions| extensions.get("KHR_texture_transform")));
+                texture
+            },
+            None => Texture::default()
+        }
     }
 
-    fn process_node(&mut self, node: &gltf::Node, buffers: &Vec<gltf::buffer::Data>, _images: &Vec<gltf::image::Data>, base_path: &String, meshes: &mut Vec<Mesh>, materials: &mut Vec<Material>) {
-        let (translation, rotation, scale) = node.transform().decomposed();
-        let _translation = Vec3::new(translation[0], translation[1], translation[2]);
-        let _rotation = Quat::from_xyzw(rotation[3], rotation[0], rotation[1], rotation[2]); // Correct order?!?!?!?
-        let _scale = Vec3::new(scale[0], scale[1], scale[2]);
-
-        match node.mesh() {
-            Some(mesh) => {
-                for primitive in mesh.primitives() {
-                    if primitive.mode() == gltf::mesh::Mode::Triangles {
-                        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-
-                        let bounds = primitive.bounding_box();
-                        let min = Vec3::from(bounds.min);
-                        let max = Vec3::from(bounds.max);
-
-                        let positions = {
-                            let iter = reader
-                                .read_positions()
-                                .expect("Failed to process mesh node. (Vertices must have positions)");
-
-                            iter.map(|arr| -> Vec3 { Vec3::from(arr) }).collect::<Vec<_>>()
-                        };
-
-                        let mut vertices: Vec<Vertex> = positions
-                            .into_iter()
-                            .map(|position| {
-                                Vertex {
-                                    position: Vec3::from(position),
-                                    ..Vertex::default()
-                                }
-                        }).collect();
-
-                        let indices = reader
-                            .read_indices()
-                            .map(|read_indices| {
-                                read_indices.into_u32().collect::<Vec<_>>()
-                            }).expect("Failed to process mesh node. (Indices are required)");
-
-                        if let Some(normals) = reader.read_normals() {
-                            for (i, normal) in normals.enumerate() {
-                                vertices[i].normal = Vec3::from(normal);
-                            }
-                        }
-
-                        let mut tex_coord_channel = 0;
-                        while let Some(tex_coords) = reader.read_tex_coords(tex_coord_channel) {
-                            for (i, tex_coord) in tex_coords.into_f32().enumerate() {
-                                match tex_coord_channel {
-                                    0 => vertices[i].tex_coord = Vec2::from(tex_coord),
-                                    1 => vertices[i].tex_coord_1 = Vec2::from(tex_coord),
-                                    _ => {}
-                                }
-                            }
-
-                            tex_coord_channel += 1;
-                        }
-
-                        if let Some(tangents) = reader.read_tangents() {
-                            for (i, tangent) in tangents.enumerate() {
-                                vertices[i].tangent = Vec4::from(tangent);
-                            }
-                        } else {
-                            // Source: 2001. http://www.terathon.com/code/tangent.html
-                            let mut tan1 = vec![Vec3::default(); vertices.len()];
-                            let mut tan2 = vec![Vec3::default(); vertices.len()];
-
-                            for i in (0..indices.len()).step_by(3) {
-                                let i1 = indices[i + 0] as usize;
-                                let i2 = indices[i + 1] as usize;
-                                let i3 = indices[i + 2] as usize;
-                            
-                                let v1 = vertices[i1].position;
-                                let v2 = vertices[i2].position;
-                                let v3 = vertices[i3].position;
-                            
-                                let w1 = vertices[i1].tex_coord;
-                                let w2 = vertices[i2].tex_coord;
-                                let w3 = vertices[i3].tex_coord;
-                            
-                                let x1 = v2.x - v1.x;
-                                let x2 = v3.x - v1.x;
-                                let y1 = v2.y - v1.y;
-                                let y2 = v3.y - v1.y;
-                                let z1 = v2.z - v1.z;
-                                let z2 = v3.z - v1.z;
-
-                                let s1 = w2.x - w1.x;
-                                let s2 = w3.x - w1.x;
-                                let t1 = w2.y - w1.y;
-                                let t2 = w3.y - w1.y;
-
-                                let r = 1.0 / (s1 * t2 - s2 * t1);
-
-                                let sdir = Vec3::new(
-                                    (t2 * x1 - t1 * x2) * r,
-                                    (t2 * y1 - t1 * y2) * r,
-                                    (t2 * z1 - t1 * z2) * r
-                                );
-
-                                let tdir = Vec3::new(
-                                    (s1 * x2 - s2 * x1) * r,
-                                    (s1 * y2 - s2 * y1) * r,
-                                    (s1 * z2 - s2 * z1) * r
-                                );
-                            
-                                tan1[i1] += sdir;
-                                tan1[i2] += sdir;
-                                tan1[i3] += sdir;
-                            
-                                tan2[i1] += tdir;
-                                tan2[i2] += tdir;
-                                tan2[i3] += tdir;
-                            }
-                        
-                            for i in 0..vertices.len() {
-                                let n = vertices[i].normal;
-                                let t = tan1[i];
-                            
-                                let xyz = (t - (n * n.dot(t))).normalize();
-                            
-                                let w;
-                                if n.cross(t).dot(tan2[i]) < 0.0 {
-                                    w = -1.0;
-                                } else {
-                                    w = 1.0;
-                                }
-
-                                vertices[i].tangent = Vec4::new(xyz.x, xyz.y, xyz.z, w);
-                            }
-                        }
-
-                        if let Some(colors) = reader.read_colors(0) {
-                            let colors = colors.into_rgba_f32();
-                            for (i, color) in colors.enumerate() {
-                                vertices[i].color = Vec4::from(color);
-                            }
-                        }
-                        
-                        let prim_material = primitive.material();
-                        let pbr = prim_material.pbr_metallic_roughness();
-                        let material_idx = primitive.material().index().unwrap_or(0);
-
-                        let material = &mut materials[material_idx];
-                        if material.index == None {
-                            material.index = Some(material_idx);
-                            material.name = prim_material.name().map(|s| s.into()).unwrap_or(String::from("Unnamed"));
-                            material.base_color_factor = Vec4::from(pbr.base_color_factor());
-                            material.metallic_factor = pbr.metallic_factor();
-                            material.roughness_factor = pbr.roughness_factor();
-                            material.emissive_factor = Vec3::from(prim_material.emissive_factor());
-
-                            if let Some(color_tex) = pbr.base_color_texture() {
-                                material.base_color_texture = self.process_tex(&color_tex.texture(), base_path);
-                            }
-
-                            if let Some(normal_tex) = prim_material.normal_texture() {
-                                material.normal_texture = self.process_tex(&normal_tex.texture(), base_path);
-                                material.normal_scale = normal_tex.scale();
-                            }
-
-                            if let Some(mr_tex) = pbr.metallic_roughness_texture() {
-                                material.metallic_roughness_texture = self.process_tex(&mr_tex.texture(), base_path);
-                            }
-
-                            if let Some(occlusion_tex) = prim_material.occlusion_texture() {
-                                material.occlusion_texture = self.process_tex(&occlusion_tex.texture(), base_path);
-                                material.occlusion_strength = occlusion_tex.strength();
-                            }
-
-                            if let Some(emissive_tex) = prim_material.emissive_texture() {
-                                material.emissive_texture = self.process_tex(&emissive_tex.texture(), base_path);
-                            }
-                        }
-
-                        meshes.push(Mesh {
-                            vertices: vertices,
-                            indices: indices,
-                            min: min,
-                            max: max,
-                            material_idx: material_idx
-                        });
-                    } else {
-                        panic!("Failed to process mesh node. (Trying to parse a non-triangle)");
+    fn process_tex_transform_json(texture: &mut Texture, transform: Option<&gltf::json::Value>) {
+        if let Some(transform) = transform {
+            texture.transform = TextureTransform {
+                offset: Self::json_vec2(transform, "offset", Vec2::ZERO),
+                rotation: Self::json_f32(transform, "rotation", 0.0),
+                scale: Self::json_vec2(transform, "scale", Vec2::ONE)
+            };
+            if let Some(tex_coord) = transform.get("texCoord").and_then(|v| v.as_u64()) {
+                texture.tex_coord = tex_coord as usize;
+            }
+        }
+    }
+
+    fn json_f32(value: &gltf::json::Value, key: &str, default: f32) -> f32 {
+        value.get(key).and_then(|v| v.as_f64()).map(|v| v as f32).unwrap_or(default)
+    }
+
+    fn json_vec2(value: &gltf::json::Value, key: &str, default: Vec2) -> Vec2 {
+        match value.get(key).and_then(|v| v.as_array()) {
+            Some(array) if array.len() == 2 => Vec2::new(
+                array[0].as_f64().unwrap_or(0.0) as f32,
+                array[1].as_f64().unwrap_or(0.0) as f32
+            ),
+            _ => default
+        }
+    }
+
+    fn json_vec3(value: &gltf::json::Value, key: &str, default: Vec3) -> Vec3 {
+        match value.get(key).and_then(|v| v.as_array()) {
+            Some(array) if array.len() == 3 => Vec3::new(
+                array[0].as_f64().unwrap_or(0.0) as f32,
+                array[1].as_f64().unwrap_or(0.0) as f32,
+                array[2].as_f64().unwrap_or(0.0) as f32
+            ),
+            _ => default
+        }
+    }
+
+    fn process_material_extensions(&mut self, document: &gltf::Document, prim_material: &gltf::Material, images: &[Option<gltf::image::Data>], base_path: &String, material: &mut Material) {
+        if let Some(clearcoat) = prim_material.extension_value("KHR_materials_clearcoat") {
+            let normal_info = clearcoat.get("clearcoatNormalTexture");
+            material.clearcoat = Some(Clearcoat {
+                factor: Self::json_f32(clearcoat, "clearcoatFactor", 0.0),
+                texture: self.process_tex_json(document, clearcoat.get("clearcoatTexture"), images, base_path, ImageImportSettings::none()),
+                roughness_factor: Self::json_f32(clearcoat, "clearcoatRoughnessFactor", 0.0),
+                roughness_texture: self.process_tex_json(document, clearcoat.get("clearcoatRoughnessTexture"), images, base_path, ImageImportSettings::none()),
+                normal_scale: normal_info.map(|info| Self::json_f32(info, "scale", 1.0)).unwrap_or(1.0),
+                normal_texture: self.process_tex_json(document, normal_info, images, base_path, ImageImportSettings::none())
+            });
+        }
+
+        if let Some(sheen) = prim_material.extension_value("KHR_materials_sheen") {
+            material.sheen = Some(Sheen {
+                color_factor: Self::json_vec3(sheen, "sheenColorFactor", Vec3::ZERO),
+                color_texture: self.process_tex_json(document, sheen.get("sheenColorTexture"), images, base_path, ImageImportSettings::Srgb),
+                roughness_factor: Self::json_f32(sheen, "sheenRoughnessFactor", 0.0),
+                roughness_texture: self.process_tex_json(document, sheen.get("sheenRoughnessTexture"), images, base_path, ImageImportSettings::none())
+            });
+        }
+
+        if let Some(specular) = prim_material.specular() {
+            material.specular = Specular {
+                factor: specular.specular_factor(),
+                texture: match specular.specular_texture() {
+                    Some(info) => self.process_tex_info(&info, images, base_path, ImageImportSettings::none()),
+                    None => Texture::default()
+                },
+                color_factor: Vec3::from(specular.specular_color_factor()),
+                color_texture: match specular.specular_color_texture() {
+                    Some(info) => self.process_tex_info(&info, images, base_path, ImageImportSettings::Srgb),
+                    None => Texture::default()
+                }
+            };
+        }
+
+        if let Some(emissive_strength) = prim_material.emissive_strength() {
+            material.emissive_strength = emissive_strength;
+        }
+
+        material.unlit = prim_material.unlit();
+
+        if let Some(ior) = prim_material.ior() {
+            material.ior = ior;
+        }
+
+        if let Some(transmission) = prim_material.transmission() {
+            material.transmission = Some(Transmission {
+                factor: transmission.transmission_factor(),
+                texture: match transmission.transmission_texture() {
+                    Some(info) => self.process_tex_info(&info, images, base_path, ImageImportSettings::none()),
+                    None => Texture::default()
+                }
+            });
+        }
+
+        if let Some(volume) = prim_material.volume() {
+            material.volume = Some(Volume {
+                thickness_factor: volume.thickness_factor(),
+                thickness_texture: match volume.thickness_texture() {
+                    Some(info) => self.process_tex_info(&info, images, base_path, ImageImportSettings::none()),
+                    None => Texture::default()
+                },
+                attenuation_distance: volume.attenuation_distance(),
+                attenuation_color: Vec3::from(volume.attenuation_color())
+            });
+        }
+    }
+
+    // Every primitive becomes a mesh, returns their indices so nodes can instance them
+    fn process_mesh(&mut self, source: &GltfSource, mesh: &gltf::Mesh, meshes: &mut Vec<Mesh>, materials: &mut Vec<Material>) -> ResourceResult<Vec<usize>> {
+        let base_path = source.base_path;
+        let images = source.images;
+        let mut primitives = Vec::new();
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&source.buffers[buffer.index()]));
+
+            let bounds = primitive.bounding_box();
+            let min = Vec3::from(bounds.min);
+            let max = Vec3::from(bounds.max);
+
+            let positions = {
+                let iter = reader
+                    .read_positions()
+                    .ok_or_else(|| ResourceError::validation(format!("Primitive {} of mesh {} has no positions", primitive.index(), mesh.index())))?;
+
+                iter.map(|arr| -> Vec3 { Vec3::from(arr) }).collect::<Vec<_>>()
+            };
+
+            // Every attribute is written per vertex, so their counts have to match the positions
+            let attributes = primitive.attributes().map(|(semantic, accessor)| (semantic.to_string(), accessor));
+            let targets = primitive.morph_targets().enumerate().flat_map(|(i, target)| {
+                [("POSITION", target.positions()), ("NORMAL", target.normals()), ("TANGENT", target.tangents())]
+                    .into_iter()
+                    .filter_map(move |(semantic, accessor)| accessor.map(|accessor| (format!("{} of morph target {}", semantic, i), accessor)))
+            });
+            for (semantic, accessor) in attributes.chain(targets) {
+                if accessor.count() != positions.len() {
+                    return Err(ResourceError::validation(format!("Primitive {} of mesh {} has {} {} values for {} positions", primitive.index(), mesh.index(), accessor.count(), semantic, positions.len())));
+                }
+            }
+
+            let mut vertices: Vec<Vertex> = positions
+                .into_iter()
+                .map(|position| {
+                    Vertex {
+                        position: Vec3::from(position),
+                        ..Vertex::default()
                     }
+            }).collect();
+
+            // Non-indexed primitives draw their vertices in order
+            let indices = reader
+                .read_indices()
+                .map(|read_indices| {
+                    read_indices.into_u32().collect::<Vec<_>>()
+                }).unwrap_or_else(|| (0..vertices.len() as u32).collect());
+
+            // The restart value is the maximum of the index type, runs between them are separate strips
+            let restart = match primitive.indices().map(|accessor| accessor.data_type()) {
+                Some(gltf::accessor::DataType::U8) => u8::MAX as u32,
+                Some(gltf::accessor::DataType::U16) => u16::MAX as u32,
+                _ => u32::MAX
+            };
+            let runs = split_restart(&indices, restart);
+            if runs.iter().flatten().any(|index| *index as usize >= vertices.len()) {
+                return Err(ResourceError::validation(format!("Primitive {} of mesh {} has indices out of range", primitive.index(), mesh.index())));
+            }
+
+            let (primitive_type, mut indices) = match primitive.mode() {
+                gltf::mesh::Mode::Triangles => (PrimitiveType::Triangles, runs.concat()),
+                gltf::mesh::Mode::TriangleStrip => (PrimitiveType::Triangles, runs.iter().flat_map(|run| triangulate_strip(run)).collect()),
+                gltf::mesh::Mode::TriangleFan => (PrimitiveType::Triangles, runs.iter().flat_map(|run| triangulate_fan(run)).collect()),
+                gltf::mesh::Mode::Lines => (PrimitiveType::Lines, runs.concat()),
+                gltf::mesh::Mode::LineStrip => (PrimitiveType::Lines, runs.iter().flat_map(|run| line_strip_to_lines(run, false)).collect()),
+                gltf::mesh::Mode::LineLoop => (PrimitiveType::Lines, runs.iter().flat_map(|run| line_strip_to_lines(run, true)).collect()),
+                gltf::mesh::Mode::Points => (PrimitiveType::Points, runs.concat())
+            };
+
+            let mut tex_coord_channel = 0;
+            while let Some(tex_coords) = reader.read_tex_coords(tex_coord_channel) {
+                for (i, tex_coord) in tex_coords.into_f32().enumerate() {
+                    match tex_coord_channel {
+                        0 => vertices[i].tex_coord = Vec2::from(tex_coord),
+                        1 => vertices[i].tex_coord_1 = Vec2::from(tex_coord),
+                        _ => {}
+                    }
+                }
+
+                tex_coord_channel += 1;
+            }
+
+            if let Some(joints) = reader.read_joints(0) {
+                for (i, joints) in joints.into_u16().enumerate() {
+                    vertices[i].joints = UVec4::new(joints[0] as u32, joints[1] as u32, joints[2] as u32, joints[3] as u32);
+                }
+            }
+
+            if let Some(weights) = reader.read_weights(0) {
+                for (i, weights) in weights.into_f32().enumerate() {
+                    let weights = Vec4::from(weights);
+                    let sum = weights.dot(Vec4::ONE);
+                    vertices[i].weights = if sum > 0.0 { weights / sum } else { weights };
+                }
+            }
+
+            if let Some(colors) = reader.read_colors(0) {
+                let colors = colors.into_rgba_f32();
+                for (i, color) in colors.enumerate() {
+                    vertices[i].color = Vec4::from(color);
+                }
+            }
+
+            // Original index of every vertex, generation below may split or unweld vertices
+            let mut sources: Vec<u32> = (0..vertices.len() as u32).collect();
+
+            let normals = reader.read_normals();
+            let has_normals = normals.is_some();
+            if let Some(normals) = normals {
+                for (i, normal) in normals.enumerate() {
+                    vertices[i].normal = Vec3::from(normal);
+                }
+            } else if primitive_type == PrimitiveType::Triangles {
+                match self.normal_generation {
+                    NormalGeneration::Flat => {
+                        let flat_sources = generate_flat_normals(&mut vertices, &mut indices);
+                        sources = flat_sources.iter().map(|i| sources[*i as usize]).collect();
+                    },
+                    NormalGeneration::Smooth => generate_smooth_normals(&mut vertices, &indices)
+                }
+            }
+
+            // Provided tangents must be ignored when the normals are generated
+            if let Some(tangents) = reader.read_tangents().filter(|_| has_normals) {
+                for (i, tangent) in tangents.enumerate() {
+                    vertices[i].tangent = Vec4::from(tangent);
+                }
+            } else if primitive_type == PrimitiveType::Triangles {
+                let tangent_sources = generate_tangents(&mut vertices, &mut indices);
+                sources = tangent_sources.iter().map(|i| sources[*i as usize]).collect();
+            }
+
+            let morph_targets: Vec<MorphTarget> = reader.read_morph_targets().map(|(positions, normals, tangents)| {
+                let remap = |deltas: Vec<Vec3>| -> Vec<Vec3> {
+                    sources.iter().map(|i| deltas[*i as usize]).collect()
+                };
+                MorphTarget {
+                    positions: positions.map(|positions| remap(positions.map(Vec3::from).collect())).unwrap_or_default(),
+                    normals: normals.map(|normals| remap(normals.map(Vec3::from).collect())).unwrap_or_default(),
+                    tangents: tangents.map(|tangents| remap(tangents.map(Vec3::from).collect())).unwrap_or_default()
+                }
+            }).collect();
+
+            let prim_material = primitive.material();
+            let pbr = prim_material.pbr_metallic_roughness();
+            let material_idx = primitive.material().index().unwrap_or(0);
+
+            let material = &mut materials[material_idx];
+            if material.index == None {
+                material.index = Some(material_idx);
+                material.name = prim_material.name().map(|s| s.into()).unwrap_or(String::from("Unnamed"));
+                material.base_color_factor = Vec4::from(pbr.base_color_factor());
+                material.metallic_factor = pbr.metallic_factor();
+                material.roughness_factor = pbr.roughness_factor();
+                material.emissive_factor = Vec3::from(prim_material.emissive_factor());
+
+                if let Some(color_tex) = pbr.base_color_texture() {
+                    material.base_color_texture = self.process_tex_info(&color_tex, images, base_path, ImageImportSettings::Srgb);
+                }
+
+                if let Some(normal_tex) = prim_material.normal_texture() {
+                    material.normal_texture = Texture {
+                        image: self.process_tex(&normal_tex.texture(), images, base_path, ImageImportSettings::none()),
+                        tex_coord: normal_tex.tex_coord() as usize,
+                        transform: TextureTransform::default()
+                    };
+                    Self::process_tex_transform_json(&mut material.normal_texture, normal_tex.extension_value("KHR_texture_transform"));
+                    material.normal_scale = normal_tex.scale();
+                }
+
+                if let Some(mr_tex) = pbr.metallic_roughness_texture() {
+                    material.metallic_roughness_texture = self.process_tex_info(&mr_tex, images, base_path, ImageImportSettings::none());
+                }
+
+                if let Some(occlusion_tex) = prim_material.occlusion_texture() {
+                    material.occlusion_texture = Texture {
+                        image: self.process_tex(&occlusion_tex.texture(), images, base_path, ImageImportSettings::none()),
+                        tex_coord: occlusion_tex.tex_coord() as usize,
+                        transform: TextureTransform::default()
+                    };
+                    Self::process_tex_transform_json(&mut material.occlusion_texture, occlusion_tex.extension_value("KHR_texture_transform"));
+                    material.occlusion_strength = occlusion_tex.strength();
+                }
+
+                if let Some(emissive_tex) = prim_material.emissive_texture() {
+                    material.emissive_texture = self.process_tex_info(&emissive_tex, images, base_path, ImageImportSettings::Srgb);
+                }
+
+                self.process_material_extensions(source.document, &prim_material, images, base_path, material);
+            }
+
+            primitives.push(meshes.len());
+            meshes.push(Mesh {
+                vertices: vertices,
+                indices: indices,
+                primitive_type: primitive_type,
+                min: min,
+                max: max,
+                material_idx: material_idx,
+                morph_targets: morph_targets,
+                weights: mesh.weights().map(|weights| weights.to_vec()).unwrap_or_default()
+            });
+        }
+        Ok(primitives)
+    }
+
+    fn process_node(node: &gltf::Node, mesh_primitives: &[Vec<usize>], lights: &mut Vec<Light>) -> Node {
+        let (translation, rotation, scale) = node.transform().decomposed();
+
+        let light = node.light().map(|punctual| {
+            let color = Vec3::from(punctual.color());
+
+            // Lights are created in the local space of the node and moved into place by the world transform
+            let mut light = match punctual.kind() {
+                gltf::khr_lights_punctual::Kind::Directional => Light::directional(Vec3::NEG_Z, color, punctual.intensity()),
+                gltf::khr_lights_punctual::Kind::Point => Light::point(Vec3::ZERO, color, punctual.intensity(), punctual.range()),
+                gltf::khr_lights_punctual::Kind::Spot { inner_cone_angle, outer_cone_angle } => {
+                    Light::spot(Vec3::ZERO, Vec3::NEG_Z, color, punctual.intensity(), punctual.range(), inner_cone_angle, outer_cone_angle)
+                }
+            };
+            if let Some(name) = punctual.name() {
+                light.name = String::from(name);
+            }
+            lights.push(light);
+            lights.len() - 1
+        });
+
+        Node {
+            name: node.name().map(|s| s.into()).unwrap_or(String::from("Unnamed")),
+            parent: None,
+            children: node.children().map(|child| child.index()).collect(),
+            translation: Vec3::from(translation),
+            rotation: Quat::from_array(rotation),
+            scale: Vec3::from(scale),
+            weights: node.weights()
+                .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+                .map(|weights| weights.to_vec())
+                .unwrap_or_default(),
+            world_matrix: Mat4::IDENTITY,
+            meshes: node.mesh().map(|mesh| mesh_primitives[mesh.index()].clone()).unwrap_or_default(),
+            light: light,
+            camera: node.camera().map(|camera| camera.index()),
+            skin: node.skin().map(|skin| skin.index())
+        }
+    }
+
+    fn process_animation(animation: &gltf::Animation, buffers: &[gltf::buffer::Data]) -> ResourceResult<Animation> {
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times: Vec<f32> = match reader.read_inputs() {
+                Some(inputs) => inputs.collect(),
+                None => continue
+            };
+
+            let (target, values): (ChannelTarget, Vec<f32>) = match reader.read_outputs() {
+                Some(gltf::animation::util::ReadOutputs::Translations(translations)) => (ChannelTarget::Translation, translations.flatten().collect()),
+                Some(gltf::animation::util::ReadOutputs::Rotations(rotations)) => (ChannelTarget::Rotation, rotations.into_f32().flatten().collect()),
+                Some(gltf::animation::util::ReadOutputs::Scales(scales)) => (ChannelTarget::Scale, scales.flatten().collect()),
+                Some(gltf::animation::util::ReadOutputs::MorphTargetWeights(weights)) => (ChannelTarget::Weights, weights.into_f32().collect()),
+                None => continue
+            };
+
+            let interpolation = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline
+            };
+
+            let elements = match interpolation {
+                Interpolation::CubicSpline => times.len() * 3,
+                _ => times.len()
+            };
+            if times.is_empty() || values.len() % elements != 0 {
+                return Err(ResourceError::validation(format!("Keyframe count mismatch in animation {}", animation.index())));
+            }
+
+            channels.push(Channel {
+                node: channel.target().node().index(),
+                target: target,
+                interpolation: interpolation,
+                components: values.len() / elements,
+                times: times,
+                values: values
+            });
+        }
+
+        Ok(Animation {
+            name: animation.name().map(|s| s.into()).unwrap_or(String::from("Unnamed")),
+            duration: channels.iter().filter_map(|channel| channel.times.last()).fold(0.0, |duration: f32, time| duration.max(*time)),
+            channels: channels
+        })
+    }
+
+    fn process_skin(skin: &gltf::Skin, buffers: &[gltf::buffer::Data]) -> Skin {
+        let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+        Skin {
+            name: skin.name().map(|s| s.into()).unwrap_or(String::from("Unnamed")),
+            joints: skin.joints().map(|joint| joint.index()).collect(),
+            inverse_bind_matrices: reader.read_inverse_bind_matrices()
+                .map(|matrices| matrices.map(|matrix| Mat4::from_cols_array_2d(&matrix)).collect())
+                .unwrap_or_default(),
+            skeleton: skin.skeleton().map(|skeleton| skeleton.index())
+        }
+    }
+
+    // Skinning indexes the joints of the skin every frame, out of range joints are rejected up front
+    fn validate_skins(model: &Model) -> ResourceResult<()> {
+        for node in &model.nodes {
+            let skin = match node.skin {
+                Some(skin) => &model.skins[skin],
+                None => continue
+            };
+            for mesh in &node.meshes {
+                let out_of_range = model.meshes[*mesh].vertices.iter().any(|vertex| {
+                    (0..4).any(|i| vertex.weights[i] > 0.0 && vertex.joints[i] as usize >= skin.joints.len())
+                });
+                if out_of_range {
+                    return Err(ResourceError::validation(format!("Node '{}' uses joints outside of skin '{}'", node.name, skin.name)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn process_camera(camera: &gltf::Camera) -> Camera {
+        let mut result = match camera.projection() {
+            gltf::camera::Projection::Perspective(perspective) => Camera {
+                name: String::from("Perspective"),
+                projection: Projection::Perspective {
+                    y_fov: perspective.yfov(),
+                    aspect_ratio: perspective.aspect_ratio(),
+                    z_near: perspective.znear(),
+                    z_far: perspective.zfar()
                 }
             },
-            None => {}
+            gltf::camera::Projection::Orthographic(orthographic) => {
+                Camera::orthographic(orthographic.xmag(), orthographic.ymag(), orthographic.znear(), orthographic.zfar())
+            }
         };
+        if let Some(name) = camera.name() {
+            result.name = String::from(name);
+        }
+        result
     }
 
-    pub fn get_model(&mut self, asset_path: String) -> Shared<Model> {
+    // The importer is picked by the file extension
+    pub fn try_get_model(&mut self, asset_path: String) -> ResourceResult<Shared<Model>> {
         match self.model_manager.get(&asset_path) {
-            Some(resource) => resource,
+            Some(resource) => Ok(resource),
             None => {
-                let (document, buffers, images) = gltf::import(asset_path.clone()).expect("Failed to get model.");
+                let extension = Path::new(&asset_path).extension()
+                    .map(|extension| extension.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                let model = match extension.as_str() {
+                    "gltf" | "glb" => self.load_gltf(&asset_path)?,
+                    "obj" => self.load_obj(&asset_path)?,
+                    "ply" | "stl" => self.load_scan(&asset_path, &extension)?,
+                    _ => return Err(ResourceError::unsupported(format!("Model extension '{}'", extension)).with_path(&asset_path))
+                };
 
-                let mut meshes = Vec::new();
-                let mut materials = vec![Material::default(); document.materials().len()];
-                if materials.len() == 0 {
-                    materials.push(Material::default());
+                let resource = Shared::new(model);
+                self.model_manager.insert(resource.clone(), asset_path);
+                Ok(resource)
+            }
+        }
+    }
+
+    // Panics when the model can't be loaded, see try_get_model
+    pub fn get_model(&mut self, asset_path: String) -> Shared<Model> {
+        self.try_get_model(asset_path).unwrap_or_else(|error| panic!("Failed to get model. ({})", error))
+    }
+
+    // External images are loaded through get_image later on, so a broken image only affects its textures
+    fn import_gltf_images(&mut self, document: &gltf::Document, buffers: &[gltf::buffer::Data], asset_path: &String) -> Vec<Option<gltf::image::Data>> {
+        let base = Path::new(asset_path).parent().unwrap_or_else(|| Path::new("./"));
+        document.images().map(|image| {
+            let source = image.source();
+            let mut image_path = format!("{}#image{}", asset_path, image.index());
+            if let gltf::image::Source::Uri { uri, .. } = source {
+                if !uri.starts_with("data:") {
+                    if base.join(uri).exists() {
+                        return None;
+                    }
+                    image_path = base.join(uri).to_string_lossy().into_owned();
                 }
-                
-                if document.nodes().len() > 0 {
-                    self.process_node(document.nodes().next().as_ref().unwrap(), &buffers, &images, &asset_path, &mut meshes, &mut materials);
+            }
+
+            match gltf::image::Data::from_source(source, Some(base), buffers) {
+                Ok(data) => Some(data),
+                Err(error) => {
+                    self.warnings.push(ResourceError::from_gltf(&image_path, error));
+                    None
                 }
+            }
+        }).collect()
+    }
 
-                let resource = Shared::new(Model {
-                    meshes: meshes,
-                    materials: materials.into_iter().map(|m| Shared::new(m)).collect()
-                });
+    fn load_gltf(&mut self, asset_path: &String) -> ResourceResult<Model> {
+        let gltf::Gltf { document, blob } = gltf::Gltf::open(asset_path).map_err(|error| ResourceError::from_gltf(asset_path, error))?;
+        let base = Path::new(asset_path).parent().unwrap_or_else(|| Path::new("./"));
+        let buffers = gltf::import_buffers(&document, Some(base), blob).map_err(|error| ResourceError::from_gltf(asset_path, error))?;
+        let images = self.import_gltf_images(&document, &buffers, asset_path);
+        let source = GltfSource {
+            document: &document,
+            buffers: &buffers,
+            images: &images,
+            base_path: asset_path
+        };
 
-                self.model_manager.insert(resource.clone(), asset_path);
-                resource
+        let mut meshes = Vec::new();
+        let mut materials = vec![Material::default(); document.materials().len()];
+        if materials.len() == 0 {
+            materials.push(Material::default());
+        }
+        
+        let mesh_primitives: Vec<Vec<usize>> = document.meshes()
+            .map(|mesh| self.process_mesh(&source, &mesh, &mut meshes, &mut materials))
+            .collect::<ResourceResult<_>>()
+            .map_err(|error| error.with_path(asset_path))?;
+
+        let mut lights = Vec::new();
+        let mut nodes: Vec<Node> = document.nodes()
+            .map(|node| Self::process_node(&node, &mesh_primitives, &mut lights))
+            .collect();
+        for i in 0..nodes.len() {
+            for child in nodes[i].children.clone() {
+                nodes[child].parent = Some(i);
             }
         }
+
+        let mut scenes: Vec<Scene> = document.scenes().map(|scene| Scene {
+            name: scene.name().map(|s| s.into()).unwrap_or(String::from("Unnamed")),
+            nodes: scene.nodes().map(|node| node.index()).collect()
+        }).collect();
+        if scenes.is_empty() {
+            scenes.push(Scene {
+                name: String::from("Default"),
+                nodes: (0..nodes.len()).filter(|i| nodes[*i].parent.is_none()).collect()
+            });
+        }
+
+        let mut model = Model {
+            meshes: meshes,
+            materials: materials.into_iter().map(|m| Shared::new(m)).collect(),
+            nodes: nodes,
+            scenes: scenes,
+            scene: document.default_scene().map(|scene| scene.index()).unwrap_or(0),
+            lights: lights,
+            cameras: document.cameras().map(|camera| Self::process_camera(&camera)).collect(),
+            skins: document.skins().map(|skin| Self::process_skin(&skin, &buffers)).collect(),
+            animations: document.animations()
+                .map(|animation| Self::process_animation(&animation, &buffers))
+                .collect::<ResourceResult<_>>()
+                .map_err(|error| error.with_path(asset_path))?
+        };
+        Self::validate_skins(&model).map_err(|error| error.with_path(asset_path))?;
+        model.update_world_transforms();
+        Ok(model)
     }
 
-    pub fn get_text(&mut self, asset_path: String) -> Shared<String> {
+    fn load_obj(&mut self, asset_path: &String) -> ResourceResult<Model> {
+        let source = fs::read_to_string(asset_path).map_err(|error| ResourceError::io(asset_path, error))?;
+        let data = parse_obj(&source).map_err(|error| error.with_path(asset_path))?;
+        let base_path = Path::new(asset_path).parent().unwrap_or_else(|| Path::new("./")).to_path_buf();
+
+        // A broken material library only affects the groups using its materials
+        let mut mtl_materials = Vec::new();
+        for library in &data.material_libraries {
+            let library_path = base_path.join(library.replace('\\', "/"));
+            let library_name = library_path.to_string_lossy().into_owned();
+            let parsed = fs::read_to_string(&library_path)
+                .map_err(|error| ResourceError::io(&library_name, error))
+                .and_then(|source| parse_mtl(&source).map_err(|error| error.with_path(&library_name)));
+
+            match parsed {
+                Ok(parsed) => {
+                    let library_base = library_path.parent().unwrap_or_else(|| Path::new("./")).to_path_buf();
+                    mtl_materials.extend(parsed.into_iter().map(|material| (material, library_base.clone())));
+                },
+                Err(error) => self.warnings.push(error)
+            }
+        }
+
+        let mut materials: Vec<Material> = mtl_materials.iter()
+            .map(|(material, library_base)| self.process_mtl_material(material, library_base))
+            .collect();
+        // Groups without a material use the default one, unknown materials get a placeholder
+        let default_idx = materials.len();
+        materials.push(Material::default());
+        let mut placeholder_idx = None;
+
+        let mut meshes = Vec::new();
+        for group in &data.groups {
+            let material_idx = match &group.material {
+                None => default_idx,
+                Some(name) => match mtl_materials.iter().position(|(material, _)| material.name == *name) {
+                    Some(index) => index,
+                    None => {
+                        self.warnings.push(ResourceError::validation(format!("Unknown material '{}'", name)).with_path(asset_path));
+                        *placeholder_idx.get_or_insert_with(|| {
+                            materials.push(Material::placeholder(self.placeholder_image.clone()));
+                            materials.len() - 1
+                        })
+                    }
+                }
+            };
+            meshes.push(build_obj_mesh(&data, group, material_idx));
+        }
+
+        let name = Path::new(asset_path).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut model = Model::from_meshes(&name, meshes, materials);
+        model.update_world_transforms();
+        Ok(model)
+    }
+
+    // PLY and STL files hold a single mesh without materials
+    fn load_scan(&mut self, asset_path: &String, extension: &str) -> ResourceResult<Model> {
+        let bytes = fs::read(asset_path).map_err(|error| ResourceError::io(asset_path, error))?;
+        let mesh = match extension {
+            "ply" => parse_ply(&bytes, self.normal_generation),
+            _ => parse_stl(&bytes)
+        }.map_err(|error| error.with_path(asset_path))?;
+
+        let name = Path::new(asset_path).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut model = Model::from_meshes(&name, vec![mesh], vec![Material::default()]);
+        model.update_world_transforms();
+        Ok(model)
+    }
+
+    fn process_mtl_texture(&mut self, texture: &MtlTexture, base_path: &Path, import_settings: ImageImportSettings) -> Texture {
+        let path = base_path.join(&texture.path);
+        let mut result = Texture::new(self.get_image_or_placeholder(path.to_string_lossy().into_owned(), import_settings));
+        // The offset is mirrored along with the flipped texture coordinates
+        result.transform.scale = texture.scale;
+        result.transform.offset = Vec2::new(texture.offset.x, 1.0 - texture.scale.y - texture.offset.y);
+        result
+    }
+
+    // Grayscale bump maps are converted to normal maps, colored ones are normal maps that some exporters write as bump.
+    // Returns the texture with the normal scale to use for it.
+    fn process_mtl_bump(&mut self, texture: &MtlTexture, base_path: &Path) -> (Texture, f32) {
+        let mut result = self.process_mtl_texture(texture, base_path, ImageImportSettings::none());
+        let normal_map = result.image.try_as_ref()
+            .filter(|image| image.is_grayscale())
+            .map(|image| image.height_to_normal_map(texture.bump_multiplier));
+        match normal_map {
+            Some(normal_map) => {
+                result.image = Shared::new(normal_map);
+                (result, 1.0)
+            },
+            None => (result, texture.bump_multiplier)
+        }
+    }
+
+    // Shininess is mapped to roughness when the material has no PBR parameters, bump maps become normal maps
+    fn process_mtl_material(&mut self, mtl: &MtlMaterial, base_path: &Path) -> Material {
+        let mut material = Material {
+            name: mtl.name.clone(),
+            base_color_factor: Vec4::from((mtl.diffuse, mtl.dissolve)),
+            emissive_factor: mtl.emissive,
+            metallic_factor: mtl.metallic.unwrap_or(0.0),
+            roughness_factor: mtl.roughness
+                .or(mtl.shininess.map(|shininess| (2.0 / (shininess.max(0.0) + 2.0)).powf(0.25)))
+                .unwrap_or(1.0),
+            ior: mtl.ior.unwrap_or(1.5),
+            ..Material::default()
+        };
+
+        if let Some(texture) = &mtl.diffuse_texture {
+            material.base_color_texture = self.process_mtl_texture(texture, base_path, ImageImportSettings::Srgb);
+        }
+        if let Some(texture) = &mtl.normal_texture {
+            material.normal_texture = self.process_mtl_texture(texture, base_path, ImageImportSettings::none());
+            material.normal_scale = texture.bump_multiplier;
+        } else if let Some(texture) = &mtl.bump_texture {
+            (material.normal_texture, material.normal_scale) = self.process_mtl_bump(texture, base_path);
+        }
+        if let Some(texture) = &mtl.emissive_texture {
+            material.emissive_texture = self.process_mtl_texture(texture, base_path, ImageImportSettings::Srgb);
+            if material.emissive_factor == Vec3::ZERO {
+                material.emissive_factor = Vec3::ONE;
+            }
+        }
+        material
+    }
+
+    pub fn try_get_text(&mut self, asset_path: String) -> ResourceResult<Shared<String>> {
         match self.text_manager.get(&asset_path) {
-            Some(resource) => resource,
+            Some(resource) => Ok(resource),
             None => {
-                let contents = fs::read_to_string(asset_path.clone()).expect("Failed to read text file.");
+                let contents = fs::read_to_string(&asset_path).map_err(|error| ResourceError::io(&asset_path, error))?;
                 let resource = Shared::new(contents);
 
                 self.text_manager.insert(resource.clone(), asset_path);
-                resource
+                Ok(resource)
             }
         }
     }
 
-    pub fn get_image(&mut self, asset_path: String, import_settings: Option<ImageImportSettings>) -> Shared<Image> {
-        match self.image_manager.get(&asset_path) {
-            Some(resource) => resource,
+    // Panics when the file can't be read, see try_get_text
+    pub fn get_text(&mut self, asset_path: String) -> Shared<String> {
+        self.try_get_text(asset_path).unwrap_or_else(|error| panic!("Failed to get text. ({})", error))
+    }
+
+    // The same file can be imported with different settings, like a texture used as both color and data
+    fn image_key(asset_path: &str, import_settings: ImageImportSettings) -> String {
+        format!("{}?{}", asset_path, import_settings.bits())
+    }
+
+    pub fn try_get_image(&mut self, asset_path: String, import_settings: Option<ImageImportSettings>) -> ResourceResult<Shared<Image>> {
+        let key = Self::image_key(&asset_path, import_settings.unwrap_or(ImageImportSettings::none()));
+        match self.image_manager.get(&key) {
+            Some(resource) => Ok(resource),
             None => {
-                let c_asset_path = CString::new(asset_path.as_bytes()).unwrap();
+                let flip = import_settings.map(|import_settings| import_settings.contains(ImageImportSettings::FlipVertical)).unwrap_or(false);
+                let mut image = Self::load_image(&asset_path, flip)?;
+                image.expand_gray();
 
-                unsafe {
-                    if let Some(import_settings) = import_settings {
-                        if !import_settings.contains(ImageImportSettings::FlipVertical) {
-                            stb_image::stb_image::bindgen::stbi_set_flip_vertically_on_load(1);
-                        }
-                    }
-            
-                    let mut width = 0;
-                    let mut height = 0;
-                    let mut channels = 0;
-                    let data = stb_image::stb_image::bindgen::stbi_load(
-                c_asset_path.as_ptr(),
-                        &mut width,
-                        &mut height,
-                        &mut channels,
-                        0,
-                    );
-                    assert!(!data.is_null(), "Failed to read image.");
-                    let data: Vec<u8> = std::slice::from_raw_parts(data, (width * height * channels) as usize).to_vec();
-
-                    let resource = Shared::new(Image::new(
-                        data,
-                        IVec2::new(width, height),
-                        channels
-                    ));
-
-                    self.image_manager.insert(resource.clone(), asset_path);
-                    resource
+                if import_settings.map(|import_settings| import_settings.contains(ImageImportSettings::Srgb)).unwrap_or(false) {
+                    image.format = image.format.to_srgb();
                 }
+
+                let resource = Shared::new(image);
+                self.image_manager.insert(resource.clone(), key);
+                Ok(resource)
+            }
+        }
+    }
+
+    // Panics when the image can't be loaded, see try_get_image
+    pub fn get_image(&mut self, asset_path: String, import_settings: Option<ImageImportSettings>) -> Shared<Image> {
+        self.try_get_image(asset_path, import_settings).unwrap_or_else(|error| panic!("Failed to get image. ({})", error))
+    }
+
+    fn load_image(asset_path: &String, flip: bool) -> ResourceResult<Image> {
+        let reader = ::image::ImageReader::open(asset_path)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(|error| ResourceError::io(asset_path, error))?;
+        let decoded = reader.decode().map_err(|error| match error {
+            ::image::ImageError::IoError(error) => ResourceError::io(asset_path, error),
+            ::image::ImageError::Unsupported(error) => ResourceError::unsupported(error.to_string()).with_path(asset_path),
+            error => ResourceError::decode(error.to_string()).with_path(asset_path)
+        })?;
+
+        let dimensions = IVec2::new(decoded.width() as i32, decoded.height() as i32);
+        let channel_count = decoded.color().channel_count() as usize;
+        let (data, component) = match decoded {
+            ::image::DynamicImage::ImageLuma8(_) | ::image::DynamicImage::ImageLumaA8(_) | ::image::DynamicImage::ImageRgb8(_) | ::image::DynamicImage::ImageRgba8(_) => {
+                (ImageData::U8(decoded.into_bytes()), TexelComponent::U8)
+            },
+            ::image::DynamicImage::ImageLuma16(image) => (ImageData::U16(image.into_raw()), TexelComponent::U16),
+            ::image::DynamicImage::ImageLumaA16(image) => (ImageData::U16(image.into_raw()), TexelComponent::U16),
+            ::image::DynamicImage::ImageRgb16(image) => (ImageData::U16(image.into_raw()), TexelComponent::U16),
+            ::image::DynamicImage::ImageRgba16(image) => (ImageData::U16(image.into_raw()), TexelComponent::U16),
+            ::image::DynamicImage::ImageRgb32F(image) => (ImageData::F32(image.into_raw()), TexelComponent::F32),
+            ::image::DynamicImage::ImageRgba32F(image) => (ImageData::F32(image.into_raw()), TexelComponent::F32),
+            decoded => return Err(ResourceError::unsupported(format!("{:?} pixels", decoded.color())).with_path(asset_path))
+        };
+
+        let format = TexelFormat::from_channel_count(component, channel_count)
+            .ok_or_else(|| ResourceError::unsupported(format!("{} channel image", channel_count)).with_path(asset_path))?;
+        let mut image = Image::new(data, dimensions, format);
+        if flip {
+            image.flip_vertical();
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> String {
+        let directory = std::env::temp_dir().join("rusterizer_tests");
+        fs::create_dir_all(&directory).unwrap();
+        directory.join(name).to_string_lossy().into_owned()
+    }
+
+    fn write_png(name: &str, color_type: ::image::ExtendedColorType, data: &[u8]) -> String {
+        let path = test_path(name);
+        ::image::save_buffer_with_format(&path, data, 2, 2, color_type, ::image::ImageFormat::Png).unwrap();
+        path
+    }
+
+    // Writes a glTF document with its buffer next to it in the temp directory
+    fn write_gltf(name: &str, json: &str, buffer: &[u8]) -> String {
+        fs::write(test_path(&format!("{}.bin", name)), buffer).unwrap();
+        let json = json.replace("BUFFER", &format!("{}.bin", name)).replace("LENGTH", &buffer.len().to_string());
+        let path = test_path(&format!("{}.gltf", name));
+        fs::write(&path, json).unwrap();
+        path
+    }
+
+    fn float_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes()).collect()
+    }
+
+    // Single indexed triangle using the given material
+    fn write_triangle(name: &str, material: &str) -> String {
+        let mut buffer = float_bytes(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        buffer.extend([0u32, 1, 2].iter().flat_map(|index| index.to_le_bytes()));
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "uri": "BUFFER", "byteLength": LENGTH }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 12 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+                { "bufferView": 1, "componentType": 5125, "count": 3, "type": "SCALAR" }
+            ],
+            "materials": [MATERIAL],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1, "material": 0 }] }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }]
+        }"#;
+        write_gltf(name, &json.replace("MATERIAL", material), &buffer)
+    }
+
+    #[test]
+    fn gray_images_are_expanded() {
+        let gray = write_png("gray.png", ::image::ExtendedColorType::L8, &[64, 64, 64, 64]);
+        let gray_alpha = write_png("gray_alpha.png", ::image::ExtendedColorType::La16, &[0x8000u16, 0x4000].repeat(4).iter().flat_map(|value| value.to_ne_bytes()).collect::<Vec<_>>());
+
+        let mut resources = Resources::init();
+        let gray = resources.get_image(gray, None);
+        assert_eq!(gray.as_ref().format, TexelFormat::RGB8);
+        let pixel = gray.as_ref().get_pixel(0.0, 0.0);
+        assert_eq!(pixel, Vec4::new(64.0 / 255.0, 64.0 / 255.0, 64.0 / 255.0, 1.0));
+
+        let gray_alpha = resources.get_image(gray_alpha, None);
+        assert_eq!(gray_alpha.as_ref().format, TexelFormat::RGBA16);
+        let pixel = gray_alpha.as_ref().get_pixel(0.0, 0.0);
+        assert_eq!(pixel.x, pixel.y);
+        assert_eq!(pixel.y, pixel.z);
+        assert_eq!(pixel.w, 0x4000 as f32 / 65535.0);
+    }
+
+    #[test]
+    fn import_settings_are_part_of_the_cache_key() {
+        let path = write_png("color.png", ::image::ExtendedColorType::Rgb8, &[255, 128, 0].repeat(4));
+
+        let mut resources = Resources::init();
+        let color = resources.get_image(path.clone(), Some(ImageImportSettings::Srgb));
+        let data = resources.get_image(path, None);
+        assert_eq!(color.as_ref().format, TexelFormat::RGB8Srgb);
+        assert_eq!(data.as_ref().format, TexelFormat::RGB8);
+    }
+
+    #[test]
+    fn embedded_images_are_expanded_and_cached_per_setting() {
+        let png = fs::read(write_png("embedded_gray.png", ::image::ExtendedColorType::L8, &[64, 64, 64, 64])).unwrap();
+        let mut buffer = float_bytes(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        buffer.extend([0u32, 1, 2].iter().flat_map(|index| index.to_le_bytes()));
+        buffer.extend(&png);
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "uri": "BUFFER", "byteLength": LENGTH }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 12 },
+                { "buffer": 0, "byteOffset": 48, "byteLength": IMAGE_LENGTH }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+                { "bufferView": 1, "componentType": 5125, "count": 3, "type": "SCALAR" }
+            ],
+            "images": [{ "bufferView": 2, "mimeType": "image/png" }],
+            "textures": [{ "source": 0 }],
+            "materials": [{ "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } }, "occlusionTexture": { "index": 0 } }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1, "material": 0 }] }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }]
+        }"#.replace("IMAGE_LENGTH", &png.len().to_string());
+        let path = write_gltf("embedded_image", &json, &buffer);
+
+        let mut resources = Resources::init();
+        let model = resources.get_model(path);
+        let model = model.as_ref();
+        let material = model.materials[0].as_ref();
+        let color = material.base_color_texture.image.as_ref();
+        let occlusion = material.occlusion_texture.image.as_ref();
+        assert_eq!(color.format, TexelFormat::RGB8Srgb);
+        assert_eq!(occlusion.format, TexelFormat::RGB8);
+        assert_eq!(occlusion.get_pixel(0.0, 0.0), Vec4::new(64.0 / 255.0, 64.0 / 255.0, 64.0 / 255.0, 1.0));
+    }
+
+    #[test]
+    fn tangents_are_generated_with_normals() {
+        // A triangle in the XY plane with tangents along its normal, which must not be used
+        let buffer = [
+            0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0,
+            0.0, 0.0, 1.0, 0.0, 0.0, 1.0
+        ];
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "uri": "BUFFER", "byteLength": LENGTH }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 48 },
+                { "buffer": 0, "byteOffset": 84, "byteLength": 24 },
+                { "buffer": 0, "byteOffset": 108, "byteLength": 12 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+                { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC4" },
+                { "bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC2" },
+                { "bufferView": 3, "componentType": 5125, "count": 3, "type": "SCALAR" }
+            ],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "TANGENT": 1, "TEXCOORD_0": 2 }, "indices": 3 }] }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }]
+        }"#;
+        let mut buffer = float_bytes(&buffer);
+        buffer.extend([0u32, 1, 2].iter().flat_map(|index| index.to_le_bytes()));
+        let path = write_gltf("tangents_without_normals", json, &buffer);
+
+        for normal_generation in [NormalGeneration::Flat, NormalGeneration::Smooth] {
+            let mut resources = Resources::init();
+            resources.normal_generation = normal_generation;
+            let model = resources.get_model(path.clone());
+            let model = model.as_ref();
+            for vertex in &model.meshes[0].vertices {
+                assert!(vertex.normal.abs_diff_eq(Vec3::Z, 1e-4));
+                assert!(vertex.tangent.xyz().abs_diff_eq(Vec3::X, 1e-4));
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn material_extensions_are_loaded() {
+        let path = write_triangle("material_extensions", r#"{
+            "extensions": {
+                "KHR_materials_clearcoat": { "clearcoatFactor": 0.5, "clearcoatRoughnessFactor": 0.25 },
+                "KHR_materials_sheen": { "sheenColorFactor": [1.0, 0.5, 0.0], "sheenRoughnessFactor": 0.75 },
+                "KHR_materials_specular": { "specularFactor": 0.5, "specularColorFactor": [0.0, 1.0, 0.0] },
+                "KHR_materials_ior": { "ior": 1.25 },
+                "KHR_materials_transmission": { "transmissionFactor": 0.75 },
+                "KHR_materials_volume": { "thicknessFactor": 2.0, "attenuationDistance": 3.0, "attenuationColor": [1.0, 0.0, 0.0] }
+            }
+        }"#);
+
+        let mut resources = Resources::init();
+        let model = resources.get_model(path);
+        let model = model.as_ref();
+        let material = model.materials[0].as_ref();
+
+        let clearcoat = material.clearcoat.as_ref().unwrap();
+        assert_eq!((clearcoat.factor, clearcoat.roughness_factor, clearcoat.normal_scale), (0.5, 0.25, 1.0));
+        let sheen = material.sheen.as_ref().unwrap();
+        assert_eq!((sheen.color_factor, sheen.roughness_factor), (Vec3::new(1.0, 0.5, 0.0), 0.75));
+        assert_eq!((material.specular.factor, material.specular.color_factor), (0.5, Vec3::Y));
+        assert_eq!(material.ior, 1.25);
+        assert_eq!(material.transmission.as_ref().unwrap().factor, 0.75);
+        let volume = material.volume.as_ref().unwrap();
+        assert_eq!((volume.thickness_factor, volume.attenuation_distance, volume.attenuation_color), (2.0, 3.0, Vec3::X));
+
+        // Materials without extensions keep the defaults of the core model
+        let path = write_triangle("material_without_extensions", "{}");
+        let model = resources.get_model(path);
+        let model = model.as_ref();
+        let material = model.materials[0].as_ref();
+        assert!(material.clearcoat.is_none() && material.sheen.is_none() && material.transmission.is_none() && material.volume.is_none());
+        assert_eq!((material.specular.factor, material.ior), (1.0, 1.5));
+    }
+
+    #[test]
+    fn unlit_and_emissive_strength_are_loaded() {
+        let path = write_triangle("unlit_emissive", r#"{
+            "emissiveFactor": [1.0, 1.0, 1.0],
+            "extensions": {
+                "KHR_materials_unlit": {},
+                "KHR_materials_emissive_strength": { "emissiveStrength": 5.0 }
+            }
+        }"#);
+
+        let mut resources = Resources::init();
+        let model = resources.get_model(path);
+        let model = model.as_ref();
+        let material = model.materials[0].as_ref();
+        assert!(material.unlit);
+        assert_eq!(material.emissive_strength, 5.0);
+    }
+
+    #[test]
+    fn joints_outside_the_skin_are_errors() {
+        let mut buffer = float_bytes(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        buffer.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0]);
+        buffer.extend(float_bytes(&[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0]));
+        buffer.extend([0u32, 1, 2].iter().flat_map(|index| index.to_le_bytes()));
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "uri": "BUFFER", "byteLength": LENGTH }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 12 },
+                { "buffer": 0, "byteOffset": 48, "byteLength": 48 },
+                { "buffer": 0, "byteOffset": 96, "byteLength": 12 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+                { "bufferView": 1, "componentType": 5121, "count": 3, "type": "VEC4" },
+                { "bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC4" },
+                { "bufferView": 3, "componentType": 5125, "count": 3, "type": "SCALAR" }
+            ],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "JOINTS_0": 1, "WEIGHTS_0": 2 }, "indices": 3 }] }],
+            "skins": [{ "joints": [1] }],
+            "nodes": [{ "mesh": 0, "skin": 0 }, { "name": "Joint" }],
+            "scenes": [{ "nodes": [0, 1] }]
+        }"#;
+        let path = write_gltf("joints_outside_the_skin", json, &buffer);
+
+        let error = Resources::init().try_get_model(path.clone()).err().unwrap();
+        assert!(matches!(error, ResourceError::Validation { .. }), "{}", error);
+        assert_eq!(error.path(), path);
+    }
+
+    #[test]
+    fn attribute_counts_must_match_positions() {
+        let buffer = float_bytes(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "uri": "BUFFER", "byteLength": LENGTH }],
+            "bufferViews": [{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+                { "bufferView": 0, "componentType": 5126, "count": 2, "type": "VEC3", "min": [0, 0, 0], "max": [1, 0, 0] },
+                { "bufferView": 0, "componentType": 5126, "count": 2, "type": "VEC2" }
+            ],
+            "meshes": [{ "primitives": [PRIMITIVE] }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }]
+        }"#;
+
+        let primitives = [
+            r#"{ "attributes": { "POSITION": 0, "TEXCOORD_0": 2 } }"#,
+            r#"{ "attributes": { "POSITION": 0 }, "targets": [{ "POSITION": 1 }] }"#
+        ];
+        for (i, primitive) in primitives.iter().enumerate() {
+            let path = write_gltf(&format!("attribute_counts_{}", i), &json.replace("PRIMITIVE", primitive), &buffer);
+            let error = Resources::init().try_get_model(path).err().unwrap();
+            assert!(matches!(error, ResourceError::Validation { .. }), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use crate::glam::*;
+use crate::resources::{Node, Vertex};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkinningMethod {
+    LinearBlend,
+    // Avoids the candy wrapper artifacts of linear blending, scale in the joints is ignored
+    DualQuaternion
+}
+
+impl SkinningMethod {
+    pub fn next(&self) -> Self {
+        match self {
+            SkinningMethod::LinearBlend => SkinningMethod::DualQuaternion,
+            SkinningMethod::DualQuaternion => SkinningMethod::LinearBlend
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Skin {
+    pub name: String,
+    // Node indices of the joints, vertices refer to joints by their position in this list
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<Mat4>,
+    pub skeleton: Option<usize>
+}
+
+impl Skin {
+    // Joint world transforms combined with the inverse bind matrices, the skinned vertices end up in world space
+    pub fn joint_matrices(&self, nodes: &[Node]) -> Vec<Mat4> {
+        self.joints.iter().enumerate().map(|(i, joint)| {
+            let inverse_bind_matrix = self.inverse_bind_matrices.get(i).copied().unwrap_or(Mat4::IDENTITY);
+            nodes[*joint].world_matrix * inverse_bind_matrix
+        }).collect()
+    }
+}
+
+struct DualQuat {
+    real: Quat,
+    dual: Quat
+}
+
+impl DualQuat {
+    fn from_mat4(matrix: &Mat4) -> Self {
+        let (_, rotation, translation) = matrix.to_scale_rotation_translation();
+        let dual = Quat::from_xyzw(translation.x, translation.y, translation.z, 0.0) * rotation * 0.5;
+        DualQuat {
+            real: rotation,
+            dual: dual
+        }
+    }
+}
+
+fn blend_matrix(vertex: &Vertex, joint_matrices: &[Mat4]) -> Mat4 {
+    let mut matrix = Mat4::ZERO;
+    for i in 0..4 {
+        let weight = vertex.weights[i];
+        if weight > 0.0 {
+            matrix += joint_matrices[vertex.joints[i] as usize] * weight;
+        }
+    }
+    matrix
+}
+
+fn blend_dual_quat(vertex: &Vertex, dual_quats: &[DualQuat]) -> DualQuat {
+    let pivot = (0..4)
+        .find(|i| vertex.weights[*i] > 0.0)
+        .map(|i| dual_quats[vertex.joints[i] as usize].real)
+        .unwrap_or(Quat::IDENTITY);
+    let mut real = Quat::from_xyzw(0.0, 0.0, 0.0, 0.0);
+    let mut dual = Quat::from_xyzw(0.0, 0.0, 0.0, 0.0);
+    for i in 0..4 {
+        let weight = vertex.weights[i];
+        if weight > 0.0 {
+            let joint = &dual_quats[vertex.joints[i] as usize];
+            // Blend along the shortest arc
+            let weight = if pivot.dot(joint.real) < 0.0 { -weight } else { weight };
+            real = real + joint.real * weight;
+            dual = dual + joint.dual * weight;
+        }
+    }
+
+    let length = real.length();
+    DualQuat {
+        real: real / length,
+        dual: dual / length
+    }
+}
+
+// Vertices without any weights are left untouched
+pub fn skin_vertices(vertices: &[Vertex], joint_matrices: &[Mat4], method: SkinningMethod) -> Vec<Vertex> {
+    match method {
+        SkinningMethod::LinearBlend => vertices.iter().map(|vertex| {
+            if vertex.weights == Vec4::ZERO {
+                return vertex.clone();
+            }
+            let matrix = blend_matrix(vertex, joint_matrices);
+            // Normals use the inverse transpose to stay perpendicular under non-uniform scale
+            Vertex {
+                position: matrix.transform_point3(vertex.position),
+                normal: matrix.inverse().transpose().transform_vector3(vertex.normal).normalize_or_zero(),
+                tangent: Vec4::from((matrix.transform_vector3(vertex.tangent.xyz()).normalize_or_zero(), vertex.tangent.w)),
+                ..vertex.clone()
+            }
+        }).collect(),
+        SkinningMethod::DualQuaternion => {
+            let dual_quats: Vec<DualQuat> = joint_matrices.iter().map(DualQuat::from_mat4).collect();
+            vertices.iter().map(|vertex| {
+                if vertex.weights == Vec4::ZERO {
+                    return vertex.clone();
+                }
+                let blended = blend_dual_quat(vertex, &dual_quats);
+                let translation = blended.dual * blended.real.conjugate() * 2.0;
+                let translation = Vec3::new(translation.x, translation.y, translation.z);
+                Vertex {
+                    position: blended.real * vertex.position + translation,
+                    normal: blended.real * vertex.normal,
+                    tangent: Vec4::from((blended.real * vertex.tangent.xyz(), vertex.tangent.w)),
+                    ..vertex.clone()
+                }
+            }).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(joints: UVec4, weights: Vec4) -> Vertex {
+        Vertex {
+            position: Vec3::X,
+            normal: Vec3::X,
+            tangent: Vec4::new(0.0, 1.0, 0.0, 1.0),
+            joints: joints,
+            weights: weights,
+            ..Vertex::default()
+        }
+    }
+
+    #[test]
+    fn single_joint_rotation() {
+        let joint_matrices = [Mat4::from_rotation_y(std::f32::consts::FRAC_PI_2)];
+        let vertices = vec![vertex(UVec4::ZERO, Vec4::X)];
+
+        for method in [SkinningMethod::LinearBlend, SkinningMethod::DualQuaternion] {
+            let skinned = &skin_vertices(&vertices, &joint_matrices, method)[0];
+            assert!(skinned.position.abs_diff_eq(Vec3::NEG_Z, 1e-5));
+            assert!(skinned.normal.abs_diff_eq(Vec3::NEG_Z, 1e-5));
+            assert!(skinned.tangent.abs_diff_eq(Vec4::new(0.0, 1.0, 0.0, 1.0), 1e-5));
+        }
+    }
+
+    #[test]
+    fn half_blend_between_joints() {
+        let joint_matrices = [Mat4::IDENTITY, Mat4::from_rotation_y(std::f32::consts::FRAC_PI_2)];
+        let vertices = vec![vertex(UVec4::new(0, 1, 0, 0), Vec4::new(0.5, 0.5, 0.0, 0.0))];
+
+        // Linear blending averages the positions and shrinks towards the joint
+        let linear = &skin_vertices(&vertices, &joint_matrices, SkinningMethod::LinearBlend)[0];
+        assert!(linear.position.abs_diff_eq(Vec3::new(0.5, 0.0, -0.5), 1e-5));
+
+        // Dual quaternions rotate halfway and keep the distance
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let dual_quaternion = &skin_vertices(&vertices, &joint_matrices, SkinningMethod::DualQuaternion)[0];
+        assert!(dual_quaternion.position.abs_diff_eq(Vec3::new(half, 0.0, -half), 1e-5));
+        assert!(dual_quaternion.normal.abs_diff_eq(Vec3::new(half, 0.0, -half), 1e-5));
+    }
+
+    #[test]
+    fn normals_under_non_uniform_scale() {
+        let joint_matrices = [Mat4::from_scale(Vec3::new(2.0, 1.0, 1.0))];
+        let mut vertices = vec![vertex(UVec4::ZERO, Vec4::X)];
+        vertices[0].normal = Vec3::new(1.0, 1.0, 0.0).normalize();
+
+        let skinned = &skin_vertices(&vertices, &joint_matrices, SkinningMethod::LinearBlend)[0];
+        assert!(skinned.normal.abs_diff_eq(Vec3::new(0.5, 1.0, 0.0).normalize(), 1e-5));
+    }
+}
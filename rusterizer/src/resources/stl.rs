@@ -0,0 +1,108 @@
+use crate::glam::*;
+use crate::resources::{Mesh, PrimitiveType, Vertex, ResourceError, ResourceResult};
+
+fn read_vec3(bytes: &[u8]) -> Vec3 {
+    let component = |i: usize| f32::from_le_bytes([bytes[i * 4], bytes[i * 4 + 1], bytes[i * 4 + 2], bytes[i * 4 + 3]]);
+    Vec3::new(component(0), component(1), component(2))
+}
+
+// Binary files may also start with "solid", so the size is checked against the triangle count
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() < 84 {
+        return false;
+    }
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    bytes.len() == 84 + count * 50 || !bytes.starts_with(b"solid")
+}
+
+fn parse_binary(bytes: &[u8]) -> ResourceResult<Vec<(Vec3, [Vec3; 3])>> {
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    if bytes.len() < 84 + count * 50 {
+        return Err(ResourceError::decode("Unexpected end of file"));
+    }
+
+    Ok(bytes[84..84 + count * 50].chunks_exact(50).map(|facet| {
+        (read_vec3(&facet[0..12]), [read_vec3(&facet[12..24]), read_vec3(&facet[24..36]), read_vec3(&facet[36..48])])
+    }).collect())
+}
+
+fn parse_ascii(bytes: &[u8]) -> ResourceResult<Vec<(Vec3, [Vec3; 3])>> {
+    let source = std::str::from_utf8(bytes).map_err(|_| ResourceError::decode("Invalid text"))?;
+    let mut facets = Vec::new();
+    let mut normal = Vec3::ZERO;
+    let mut corners: Vec<Vec3> = Vec::new();
+
+    for line in source.lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let parse = |values: &[&str]| -> ResourceResult<Vec3> {
+            let values: Vec<f32> = values.iter().map(|value| value.parse().map_err(|_| ResourceError::decode("Invalid number"))).collect::<ResourceResult<_>>()?;
+            if values.len() < 3 {
+                return Err(ResourceError::decode("Expected 3 components"));
+            }
+            Ok(Vec3::new(values[0], values[1], values[2]))
+        };
+
+        match tokens.as_slice() {
+            ["facet", "normal", values @ ..] => {
+                normal = parse(values)?;
+                corners.clear();
+            },
+            ["vertex", values @ ..] => corners.push(parse(values)?),
+            ["endfacet", ..] => {
+                // Polygons with more than three corners are fanned
+                for i in 2..corners.len() {
+                    facets.push((normal, [corners[0], corners[i - 1], corners[i]]));
+                }
+            },
+            _ => {}
+        }
+    }
+    Ok(facets)
+}
+
+// Facets don't share vertices, the stored normal is replaced when it's missing or degenerate
+pub fn parse_stl(bytes: &[u8]) -> ResourceResult<Mesh> {
+    let facets = if is_binary(bytes) { parse_binary(bytes)? } else { parse_ascii(bytes)? };
+
+    let mut vertices = Vec::with_capacity(facets.len() * 3);
+    for (normal, corners) in facets {
+        let normal = if normal.length_squared() > 0.0 {
+            normal.normalize()
+        } else {
+            (corners[1] - corners[0]).cross(corners[2] - corners[0]).normalize_or_zero()
+        };
+        vertices.extend(corners.iter().map(|position| Vertex {
+            position: *position,
+            normal: normal,
+            ..Vertex::default()
+        }));
+    }
+
+    let indices = (0..vertices.len() as u32).collect();
+    Ok(Mesh::new(vertices, indices, PrimitiveType::Triangles, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_and_binary_facets() {
+        let ascii = "solid part\n facet normal 0 0 0\n  outer loop\n   vertex 0 0 0\n   vertex 1 0 0\n   vertex 0 1 0\n  endloop\n endfacet\nendsolid part\n";
+
+        // Binary header starting with "solid" like some exporters write
+        let mut binary = b"solid exported".to_vec();
+        binary.resize(80, 0);
+        binary.extend_from_slice(&1u32.to_le_bytes());
+        for value in [0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            binary.extend_from_slice(&value.to_le_bytes());
+        }
+        binary.extend_from_slice(&[0, 0]);
+
+        for mesh in [parse_stl(ascii.as_bytes()).unwrap(), parse_stl(&binary).unwrap()] {
+            assert_eq!(mesh.vertices.len(), 3);
+            assert_eq!(mesh.indices, vec![0, 1, 2]);
+            assert!(mesh.vertices.iter().all(|vertex| vertex.normal == Vec3::Z));
+        }
+    }
+}
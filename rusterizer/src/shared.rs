@@ -1,11 +1,20 @@
 pub use std::rc::Rc;
 pub use std::cell::{RefCell, Ref, RefMut};
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Shared<T> {
     value: Option<Rc<RefCell<T>>>
 }
 
+// Cloning shares the value, so it shouldn't require T: Clone like a derive would
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared {
+            value: self.value.clone()
+        }
+    }
+}
+
 impl<T> Shared<T> {
     pub fn new(value: T) -> Self {
         Shared {